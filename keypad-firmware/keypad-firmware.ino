@@ -1,17 +1,33 @@
 #include <Bounce2.h>
 #include <EEPROM.h>
 
+// pins are in slot order, a board.json on the host describes where each key sits
 const int NUM_KEYS = 6;
 const int buttonPins[NUM_KEYS] = {23, 22, 0, 1, 2, 3};
 const int ledPins[NUM_KEYS] = {20, 17, 16, 10, 9, 6};
 const int ledIntensity = 30;
 const int debounceInterval = 10;
+const byte KEY_BYTES = 8;
+// highest key code the keyboard descriptor covers
+const int MAX_KEY = KEY_F24;
 
 const char READ_KEYS = 'R';
 const char WRITE_KEYS = 'W';
+const char READ_KEY = 'r';
+const char WRITE_KEY = 'w';
+const char LOAD_KEYS = 'L';
+const char LOAD_KEY = 'l';
+const char PERSIST = 'P';
+const char READ_PROFILE_ID = 'I';
+const char WRITE_PROFILE_ID = 'J';
+const char READ_MAX_KEY = 'M';
 const char FLASH = 'F';
 const char HELLO = 'H';
 const char ACK = 'A';
+// sent after the ACK for HELLO, firmware from before single slots and profile IDs sends nothing
+const byte PROTOCOL_VERSION = 1;
+// sent instead of the usual reply when a command can't be carried out
+const char NAK = 'N';
 
 Bounce buttons[NUM_KEYS];
 
@@ -24,6 +40,11 @@ struct KeyCombo {
 
 KeyCombo keyCombos[NUM_KEYS];
 
+// UUID of the profile the combos came from, stored after the combos in EEPROM
+const int PROFILE_ID_BYTES = 16;
+const int PROFILE_ID_ADDRESS = NUM_KEYS * sizeof(KeyCombo);
+byte profileId[PROFILE_ID_BYTES];
+
 unsigned long flashLEDsStart = 0;
 byte flashingMask = 255;
 bool flashingLEDs = false;
@@ -46,8 +67,29 @@ void setup() {
 
 void storeKeyCombos() {
   for (int i = 0; i < NUM_KEYS; i++) {
-    int address = i * sizeof(KeyCombo);
-    EEPROM.put(address, keyCombos[i]);
+    storeKeyCombo(i);
+  }
+}
+
+void storeKeyCombo(int i) {
+  int address = i * sizeof(KeyCombo);
+  EEPROM.put(address, keyCombos[i]);
+}
+
+// only slots that differ from what's already in EEPROM are written
+void persistKeyCombos() {
+  for (int i = 0; i < NUM_KEYS; i++) {
+    KeyCombo stored;
+    EEPROM.get(i * sizeof(KeyCombo), stored);
+    if (memcmp(&stored, &keyCombos[i], sizeof(KeyCombo)) != 0) {
+      storeKeyCombo(i);
+    }
+  }
+
+  byte storedId[PROFILE_ID_BYTES];
+  EEPROM.get(PROFILE_ID_ADDRESS, storedId);
+  if (memcmp(storedId, profileId, PROFILE_ID_BYTES) != 0) {
+    EEPROM.put(PROFILE_ID_ADDRESS, profileId);
   }
 }
 
@@ -56,6 +98,7 @@ void loadKeyCombos() {
     int address = i * sizeof(KeyCombo);
     EEPROM.get(address, keyCombos[i]);
   }
+  EEPROM.get(PROFILE_ID_ADDRESS, profileId);
 }
 
 void loop() {
@@ -80,12 +123,38 @@ void handleSerial() {
     switch (received) {
       case HELLO:
         Serial.write(ACK);
+        Serial.write(PROTOCOL_VERSION);
         break;
       case READ_KEYS:
         sendKeyCombos();
         break;
       case WRITE_KEYS:
-        setCombos();
+        setCombos(true);
+        break;
+      case LOAD_KEYS:
+        setCombos(false);
+        break;
+      case READ_KEY:
+        sendSingleKeyCombo();
+        break;
+      case WRITE_KEY:
+        setSingleCombo(true);
+        break;
+      case LOAD_KEY:
+        setSingleCombo(false);
+        break;
+      case PERSIST:
+        persistKeyCombos();
+        Serial.write(ACK);
+        break;
+      case READ_PROFILE_ID:
+        Serial.write(profileId, PROFILE_ID_BYTES);
+        break;
+      case WRITE_PROFILE_ID:
+        setProfileId();
+        break;
+      case READ_MAX_KEY:
+        sendKey(MAX_KEY);
         break;
       case FLASH:
         flashKeys();
@@ -96,48 +165,108 @@ void handleSerial() {
 
 void sendKeyCombos() {
   for (int i = 0; i < NUM_KEYS; i++) {
-    sendKey(keyCombos[i].modifier_one);
-    sendKey(keyCombos[i].key_one);
-    sendKey(keyCombos[i].modifier_two);
-    sendKey(keyCombos[i].key_two);
+    sendComboBytes(keyCombos[i]);
   }
 }
 
+void sendComboBytes(KeyCombo combo) {
+  sendKey(combo.modifier_one);
+  sendKey(combo.key_one);
+  sendKey(combo.modifier_two);
+  sendKey(combo.key_two);
+}
+
 void sendKey(int key) {
   Serial.write(lowByte(key));
   Serial.write(highByte(key));
 }
 
-void setCombos() {
+// loaded combos only last until the next power cycle unless they're persisted
+void setCombos(bool persist) {
   readKeyCombosFromSerial();
-  storeKeyCombos();
+  if (persist) {
+    storeKeyCombos();
+  }
   sendKeyCombos();
 }
 
 void readKeyCombosFromSerial() {
-  const byte KEY_BYTES = 8;
   const byte BUF_SIZE = NUM_KEYS * KEY_BYTES;
   char buf[BUF_SIZE];
   int readLen = Serial.readBytes(buf, BUF_SIZE);
   if (readLen == BUF_SIZE) {
     for (int i = 0; i < NUM_KEYS; i++) {
-      int startIdx = KEY_BYTES * i;
-      int modifier_one = buf[startIdx + 1] << 8 | buf[startIdx];
-      int key_one = buf[startIdx + 3] << 8 | buf[startIdx + 2];
-      int modifier_two = buf[startIdx + 5] << 8 | buf[startIdx + 4];
-      int key_two = buf[startIdx + 7] << 8 | buf[startIdx + 6];
-      keyCombos[i] = KeyCombo {
-        modifier_one,
-        key_one,
-        modifier_two,
-        key_two
-      };
+      keyCombos[i] = parseKeyCombo(buf + KEY_BYTES * i);
     }
 
     startFlashingLEDs();
   }
 }
 
+KeyCombo parseKeyCombo(char *buf) {
+  int modifier_one = buf[1] << 8 | buf[0];
+  int key_one = buf[3] << 8 | buf[2];
+  int modifier_two = buf[5] << 8 | buf[4];
+  int key_two = buf[7] << 8 | buf[6];
+  return KeyCombo {
+    modifier_one,
+    key_one,
+    modifier_two,
+    key_two
+  };
+}
+
+// the slot index follows the single key commands, -1 if it's missing or out of range
+int readSlotFromSerial() {
+  char slot;
+  if (Serial.readBytes(&slot, 1) != 1 || slot < 0 || slot >= NUM_KEYS) {
+    return -1;
+  }
+  return slot;
+}
+
+void sendSingleKeyCombo() {
+  int slot = readSlotFromSerial();
+  if (slot >= 0) {
+    sendComboBytes(keyCombos[slot]);
+  } else {
+    Serial.write(NAK);
+  }
+}
+
+// only the changed slot is written to EEPROM, then echoed back like WRITE_KEYS
+void setSingleCombo(bool persist) {
+  int slot = readSlotFromSerial();
+  // the combo is read even for a bad slot, otherwise its bytes would be taken as commands
+  char buf[KEY_BYTES];
+  int readLen = Serial.readBytes(buf, KEY_BYTES);
+  if (slot < 0) {
+    Serial.write(NAK);
+    return;
+  }
+
+  if (readLen == KEY_BYTES) {
+    keyCombos[slot] = parseKeyCombo(buf);
+    if (persist) {
+      storeKeyCombo(slot);
+    }
+    startFlashingLEDs();
+    flashingMask = 1 << slot;
+  }
+  sendComboBytes(keyCombos[slot]);
+}
+
+// like loaded combos, the ID only survives a power cycle once it's persisted
+void setProfileId() {
+  byte buf[PROFILE_ID_BYTES];
+  if (Serial.readBytes((char *)buf, PROFILE_ID_BYTES) == PROFILE_ID_BYTES) {
+    memcpy(profileId, buf, PROFILE_ID_BYTES);
+    Serial.write(ACK);
+  } else {
+    Serial.write(NAK);
+  }
+}
+
 void flashKeys() {
   startFlashingLEDs();
   flashingMask = Serial.read();
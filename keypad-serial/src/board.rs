@@ -0,0 +1,146 @@
+use std::{fs::read_to_string, path::Path};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub const BOARD_FILE: &str = "board.json";
+
+// the serial protocol always carries six slots, a board can use fewer of them
+pub const MAX_KEYS: usize = 6;
+
+#[derive(Error, Debug)]
+pub enum BoardError {
+    #[error("Error reading board file: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Board file is invalid: {0}")]
+    SerdeError(#[from] serde_json::Error),
+    #[error("Board has no keys")]
+    NoKeys,
+    #[error("Board has {0} keys but the keypad only supports {}", MAX_KEYS)]
+    TooManyKeys(usize),
+    #[error("Key '{0}' is outside the board's grid")]
+    OutsideGrid(String),
+    #[error("Keys '{0}' and '{1}' are in the same place")]
+    SamePosition(String, String),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct Board {
+    pub name: String,
+    pub columns: usize,
+    pub rows: usize,
+    // in slot order, keys[0] is key 1 on the wire
+    pub keys: Vec<BoardKey>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct BoardKey {
+    pub name: String,
+    pub column: usize,
+    pub row: usize,
+    #[serde(default = "has_led")]
+    pub led: bool,
+}
+
+impl Board {
+    // a board.json in dir, or the standard board when there isn't one
+    pub fn locate(dir: &Path) -> Result<Board, BoardError> {
+        let path = dir.join(BOARD_FILE);
+        match path.exists() {
+            true => Board::load(&path),
+            false => Ok(Board::default()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Board, BoardError> {
+        let board: Board = serde_json::from_str(&read_to_string(path)?)?;
+        board.validate()?;
+        Ok(board)
+    }
+
+    pub fn validate(&self) -> Result<(), BoardError> {
+        if self.keys.is_empty() {
+            return Err(BoardError::NoKeys);
+        }
+        if self.keys.len() > MAX_KEYS {
+            return Err(BoardError::TooManyKeys(self.keys.len()));
+        }
+        for (idx, key) in self.keys.iter().enumerate() {
+            if key.column >= self.columns || key.row >= self.rows {
+                return Err(BoardError::OutsideGrid(key.name.clone()));
+            }
+            let same_place = |k: &&BoardKey| k.column == key.column && k.row == key.row;
+            if let Some(other) = self.keys[idx + 1..].iter().find(same_place) {
+                return Err(BoardError::SamePosition(
+                    key.name.clone(),
+                    other.name.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+// the original keypad, two rows of three with an LED under every key
+impl Default for Board {
+    fn default() -> Self {
+        let keys = (0..MAX_KEYS)
+            .map(|slot| BoardKey {
+                name: format!("Key {}", slot + 1),
+                column: slot % 3,
+                row: slot / 3,
+                led: true,
+            })
+            .collect();
+        Self {
+            name: "Keypad".into(),
+            columns: 3,
+            rows: 2,
+            keys,
+        }
+    }
+}
+
+fn has_led() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boards_are_validated() {
+        assert!(Board::default().validate().is_ok());
+
+        let mut board: Board = serde_json::from_str(
+            r#"{
+                "name": "Macro pad",
+                "columns": 2,
+                "rows": 2,
+                "keys": [
+                    { "name": "Mute", "column": 0, "row": 0 },
+                    { "name": "Deafen", "column": 1, "row": 0, "led": false },
+                    { "name": "Push to talk", "column": 0, "row": 1 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert!(board.validate().is_ok());
+        assert_eq!(board.key_count(), 3);
+        assert!(board.keys[0].led && !board.keys[1].led);
+
+        board.keys[2].column = 1;
+        board.keys[2].row = 0;
+        assert!(matches!(
+            board.validate(),
+            Err(BoardError::SamePosition(_, _))
+        ));
+        board.keys[2].row = 2;
+        assert!(matches!(board.validate(), Err(BoardError::OutsideGrid(_))));
+    }
+}
@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+
+use super::{
+    board::Board,
+    keys::{KeyCombo, KeyLabel},
+};
+
+// sizes are in millimetres so the keys print at their real size on A4
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 10.0;
+const GAP: f32 = 8.0;
+const TITLE_HEIGHT: f32 = 8.0;
+const KEY_PITCH: f32 = 19.0;
+const KEY_SIZE: f32 = 18.0;
+const FONT_SIZE: f32 = 3.0;
+
+#[derive(Debug, Clone, Default)]
+pub struct CheatSheetEntry {
+    pub name: String,
+    pub combos: Vec<Option<KeyCombo>>,
+    pub labels: Vec<KeyLabel>,
+}
+
+// one SVG per A4 page, with as many profiles on each page as fit
+pub fn render_cheat_sheet(board: &Board, entries: &[CheatSheetEntry]) -> Vec<String> {
+    let board_width = board.columns as f32 * KEY_PITCH;
+    let board_height = TITLE_HEIGHT + board.rows as f32 * KEY_PITCH;
+    // a board bigger than the page still gets one to itself, running off the edge
+    let per_row = (((PAGE_WIDTH - 2.0 * MARGIN + GAP) / (board_width + GAP)) as usize).max(1);
+    let rows_per_page =
+        (((PAGE_HEIGHT - 2.0 * MARGIN + GAP) / (board_height + GAP)) as usize).max(1);
+    let per_page = per_row * rows_per_page;
+
+    let mut pages = Vec::new();
+    for chunk in entries.chunks(per_page) {
+        let mut svg = page_header();
+        for (idx, entry) in chunk.iter().enumerate() {
+            let column = idx % per_row;
+            let row = idx / per_row;
+            let x = MARGIN + column as f32 * (board_width + GAP);
+            let y = MARGIN + row as f32 * (board_height + GAP);
+            svg.push_str(&render_board(board, entry, x, y));
+        }
+        svg.push_str("</svg>\n");
+        pages.push(svg);
+    }
+    if pages.is_empty() {
+        pages.push(page_header() + "</svg>\n");
+    }
+    pages
+}
+
+// the first page keeps the chosen name, later ones get their page number, e.g. keys-2.svg
+pub fn cheat_sheet_page_path(path: &Path, page: usize) -> PathBuf {
+    if page == 0 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}-{}", stem, page + 1);
+    if let Some(extension) = path.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+fn page_header() -> String {
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}mm" height="{h}mm" "#,
+            r#"viewBox="0 0 {w} {h}" font-family="Segoe UI, sans-serif">"#,
+            "\n"
+        ),
+        w = PAGE_WIDTH,
+        h = PAGE_HEIGHT
+    )
+}
+
+fn render_board(board: &Board, entry: &CheatSheetEntry, x: f32, y: f32) -> String {
+    let mut svg = format!(
+        "<text x=\"{}\" y=\"{}\" font-size=\"{}\" font-weight=\"bold\">{}</text>\n",
+        x,
+        y + TITLE_HEIGHT - 3.0,
+        FONT_SIZE + 1.0,
+        escape(&entry.name)
+    );
+    for (slot, key) in board.keys.iter().enumerate() {
+        let key_x = x + key.column as f32 * KEY_PITCH;
+        let key_y = y + TITLE_HEIGHT + key.row as f32 * KEY_PITCH;
+        svg.push_str(&format!(
+            concat!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{s}\" height=\"{s}\" rx=\"1.5\" ",
+                "fill=\"none\" stroke=\"black\" stroke-width=\"0.2\"/>\n"
+            ),
+            key_x,
+            key_y,
+            s = KEY_SIZE
+        ));
+        let lines = key_lines(entry, slot);
+        svg.push_str(&render_lines(
+            &lines,
+            key_x + KEY_SIZE / 2.0,
+            key_y + KEY_SIZE / 2.0,
+        ));
+    }
+    svg
+}
+
+// the label if there is one, otherwise each press of the combo on its own line
+fn key_lines(entry: &CheatSheetEntry, slot: usize) -> Vec<String> {
+    let label = entry.labels.get(slot).and_then(|l| l.label.as_ref());
+    match (label, entry.combos.get(slot)) {
+        (Some(label), _) => vec![label.clone()],
+        (None, Some(Some(combo))) => {
+            let mut lines = vec![combo.one.to_string()];
+            lines.extend(combo.two.iter().map(|p| p.to_string()));
+            lines
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn render_lines(lines: &[String], center_x: f32, center_y: f32) -> String {
+    let longest = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    // roughly 0.55em per character, shrunk until the longest line fits the key
+    let size = FONT_SIZE.min((KEY_SIZE - 2.0) / (longest as f32 * 0.55).max(1.0));
+    let top = center_y - (lines.len() as f32 - 1.0) * size * 0.6;
+
+    let mut svg = String::new();
+    for (idx, line) in lines.iter().enumerate() {
+        svg.push_str(&format!(
+            concat!(
+                "<text x=\"{}\" y=\"{}\" font-size=\"{}\" text-anchor=\"middle\" ",
+                "dominant-baseline=\"middle\">{}</text>\n"
+            ),
+            center_x,
+            top + idx as f32 * size * 1.2,
+            size,
+            escape(line)
+        ));
+    }
+    svg
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_binding;
+
+    fn entry(name: &str) -> CheatSheetEntry {
+        let combos = ["Ctrl + C", "Ctrl + V", "None", "F5, Enter", "A", "B"];
+        let mut labels = vec![KeyLabel::default(); 6];
+        labels[1].label = Some("Paste <plain>".into());
+        CheatSheetEntry {
+            name: name.into(),
+            combos: combos.iter().map(|c| parse_binding(c).unwrap()).collect(),
+            labels,
+        }
+    }
+
+    #[test]
+    fn keys_show_labels_or_combos() {
+        let pages = render_cheat_sheet(&Board::default(), &[entry("IDE & tools")]);
+        assert_eq!(pages.len(), 1);
+        let svg = &pages[0];
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(">IDE &amp; tools</text>"));
+        assert!(svg.contains(">Ctrl + C</text>"));
+        assert!(svg.contains(">Paste &lt;plain&gt;</text>"));
+        assert!(!svg.contains(">Ctrl + V</text>"));
+        assert!(svg.contains(">F5</text>") && svg.contains(">Enter</text>"));
+        assert_eq!(svg.matches("<rect").count(), 6);
+        assert!(svg.contains("height=\"297mm\""));
+    }
+
+    #[test]
+    fn full_pages_continue_on_the_next_page() {
+        let entries: Vec<_> = (0..16).map(|i| entry(&i.to_string())).collect();
+        let pages = render_cheat_sheet(&Board::default(), &entries);
+        assert_eq!(pages.len(), 2);
+        let keys: usize = pages.iter().map(|p| p.matches("<rect").count()).sum();
+        assert_eq!(keys, 16 * 6);
+        assert!(pages.iter().all(|p| p.contains("height=\"297mm\"")));
+
+        let mut board = Board::default();
+        board.keys.truncate(4);
+        let pages = render_cheat_sheet(&board, &entries[..1]);
+        assert_eq!(pages[0].matches("<rect").count(), 4);
+
+        let path = Path::new("out/keys.svg");
+        assert_eq!(cheat_sheet_page_path(path, 0), path);
+        assert_eq!(cheat_sheet_page_path(path, 1), Path::new("out/keys-2.svg"));
+    }
+
+    #[test]
+    fn oversized_boards_still_render() {
+        let entries: Vec<_> = (0..3).map(|i| entry(&i.to_string())).collect();
+        // too wide for two side by side, so they're stacked
+        let wide = Board {
+            columns: 12,
+            ..Board::default()
+        };
+        let pages = render_cheat_sheet(&wide, &entries);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].matches("<text x=\"10\"").count(), 3);
+
+        let huge = Board { rows: 20, ..wide };
+        let pages = render_cheat_sheet(&huge, &entries);
+        assert_eq!(pages.len(), 3);
+        assert!(pages.iter().all(|p| p.matches("<rect").count() == 6));
+    }
+}
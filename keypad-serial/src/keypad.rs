@@ -1,15 +1,30 @@
 use crate::KeyPress;
 
-use super::keys::{Key, KeyCombo, ModifierKey};
+use super::board::MAX_KEYS;
+use super::keys::{binding_to_string, Key, KeyCombo, ModifierKey};
 use num_traits::cast::FromPrimitive;
-use serialport::SerialPort;
-use std::convert::TryInto;
+use serialport::{ClearBuffer, SerialPort};
+use std::{
+    convert::TryInto,
+    fmt::{Display, Formatter},
+    thread::sleep,
+    time::Duration,
+};
 use thiserror::Error;
+use uuid::Uuid;
 
 const HELLO: u8 = 'H' as u8;
 const ACK: u8 = 'A' as u8;
 const READ_KEYS: u8 = 'R' as u8;
 const WRITE_KEYS: u8 = 'W' as u8;
+const READ_KEY: u8 = 'r' as u8;
+const WRITE_KEY: u8 = 'w' as u8;
+const LOAD_KEYS: u8 = 'L' as u8;
+const LOAD_KEY: u8 = 'l' as u8;
+const PERSIST: u8 = 'P' as u8;
+const READ_PROFILE_ID: u8 = 'I' as u8;
+const WRITE_PROFILE_ID: u8 = 'J' as u8;
+const READ_MAX_KEY: u8 = 'M' as u8;
 const FLASH: u8 = 'F' as u8;
 
 #[derive(Error, Debug)]
@@ -26,10 +41,134 @@ pub enum KeypadError {
     NoDeviceFound,
     #[error("Device returned unexpected key count")]
     WrongKeyCountFromDevice,
+    #[error("There is no key {}", .0 + 1)]
+    InvalidSlot(usize),
+    #[error("Device did not store the combos that were sent: {0}")]
+    VerifyFailed(ComboDiff),
+    #[error("{0}")]
+    InvalidSlotData(#[from] SlotError),
+    #[error("Keypad firmware doesn't support {0}")]
+    UnsupportedByFirmware(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SlotErrorKind {
+    // erased EEPROM, nothing but 0xFFFF and 0x0000 words
+    Unconfigured,
+    Corrupt,
+}
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub struct SlotError {
+    pub slot: usize,
+    pub bytes: [u8; 8],
+    pub kind: SlotErrorKind,
+}
+
+// each slot decodes on its own so one bad slot doesn't hide the others
+pub type SlotCombos = [Result<Option<KeyCombo>, SlotError>; MAX_KEYS];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SlotDiff {
+    pub slot: usize,
+    pub sent: Option<KeyCombo>,
+    pub echoed: Option<KeyCombo>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ComboDiff {
+    pub slots: Vec<SlotDiff>,
+}
+
+impl ComboDiff {
+    pub fn between(
+        sent: &[Option<KeyCombo>; MAX_KEYS],
+        echoed: &[Option<KeyCombo>; MAX_KEYS],
+    ) -> Self {
+        let slots = sent
+            .iter()
+            .zip(echoed.iter())
+            .enumerate()
+            .filter(|(_, (sent, echoed))| sent != echoed)
+            .map(|(slot, (sent, echoed))| SlotDiff {
+                slot,
+                sent: sent.clone(),
+                echoed: echoed.clone(),
+            })
+            .collect();
+        ComboDiff { slots }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+impl Display for SlotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        match self.kind {
+            SlotErrorKind::Unconfigured => write!(f, "key {} is not configured", self.slot + 1)?,
+            SlotErrorKind::Corrupt => write!(f, "key {} has invalid data", self.slot + 1)?,
+        }
+        write!(f, " ({})", bytes.join(" "))
+    }
+}
+
+impl Display for SlotDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "key {}: sent {}, device has {}",
+            self.slot + 1,
+            binding_to_string(self.sent.as_ref()),
+            binding_to_string(self.echoed.as_ref())
+        )
+    }
+}
+
+impl Display for ComboDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let slots: Vec<String> = self.slots.iter().map(|d| d.to_string()).collect();
+        write!(f, "{}", slots.join("; "))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            delay: Duration::from_millis(100),
+        }
+    }
+}
+
+// Volatile combos are lost on power cycle and the device goes back to the ones in EEPROM
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ApplyMode {
+    Volatile,
+    Persistent,
+}
+
+// firmware from before the protocol version only answers HELLO with ACK
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Protocol {
+    // full reads and writes of the stored combos, and flashing
+    Legacy,
+    // single slots, loading without storing and profile IDs
+    Slots,
 }
 
 pub struct Keypad {
     serial_port: Box<dyn SerialPort>,
+    retry_policy: RetryPolicy,
+    protocol: Protocol,
 }
 
 impl Keypad {
@@ -48,8 +187,12 @@ impl Keypad {
             let name: &str = port.port_name.as_ref();
             log::info!("Trying port {}...", name);
             if let Ok(mut port) = serialport::open_with_settings(name, &settings) {
-                if handshake(&mut *port).is_ok() {
-                    return Ok(Keypad { serial_port: port });
+                if let Ok(protocol) = handshake(&mut *port) {
+                    return Ok(Keypad {
+                        serial_port: port,
+                        retry_policy: RetryPolicy::default(),
+                        protocol,
+                    });
                 }
             }
         }
@@ -57,33 +200,271 @@ impl Keypad {
         Err(KeypadError::NoDeviceFound)
     }
 
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    // writes the combos and checks the device's echo, retrying as configured
     pub fn send_combos_to_device(
         &mut self,
-        combos: [KeyCombo; 6],
-    ) -> Result<[KeyCombo; 6], KeypadError> {
+        combos: [Option<KeyCombo>; MAX_KEYS],
+        mode: ApplyMode,
+    ) -> Result<[Option<KeyCombo>; MAX_KEYS], KeypadError> {
+        self.with_retries(|keypad| {
+            let echoed = keypad.write_combos(&combos, mode)?;
+            let diff = ComboDiff::between(&combos, &echoed);
+            match diff.is_empty() {
+                true => Ok(echoed),
+                false => Err(KeypadError::VerifyFailed(diff)),
+            }
+        })
+    }
+
+    // writes the combos that differ from the device, then the ID of the profile they belong to,
+    // and returns the combos the device echoed back
+    pub fn apply_profile(
+        &mut self,
+        id: Uuid,
+        combos: [Option<KeyCombo>; MAX_KEYS],
+        mode: ApplyMode,
+    ) -> Result<[Option<KeyCombo>; MAX_KEYS], KeypadError> {
+        if self.protocol == Protocol::Legacy {
+            log::warn!("Keypad firmware doesn't support profile IDs, only writing combos");
+            return self.send_combos_to_device(combos, mode);
+        }
+        let applied = self.write_changed_combos(&combos, mode)?;
+        self.set_profile_id(id)?;
+        if mode == ApplyMode::Persistent {
+            // unchanged slots and the ID may only have been loaded, not stored
+            self.persist_combos()?;
+        }
+        Ok(applied)
+    }
+
+    fn write_changed_combos(
+        &mut self,
+        combos: &[Option<KeyCombo>; MAX_KEYS],
+        mode: ApplyMode,
+    ) -> Result<[Option<KeyCombo>; MAX_KEYS], KeypadError> {
+        let current = match self.read_combos_from_device() {
+            Ok(current) => current,
+            Err(e) => {
+                log::warn!("Unable to read current combos, writing all: {}", e);
+                let _ = self.serial_port.clear(ClearBuffer::Input);
+                return self.send_combos_to_device(combos.clone(), mode);
+            }
+        };
+
+        // slots the device couldn't decode are always rewritten
+        let mut applied = combos.clone();
+        let mut changed = 0;
+        for slot in 0..combos.len() {
+            match &current[slot] {
+                Ok(combo) if combo == &combos[slot] => applied[slot] = combo.clone(),
+                _ => match self.send_combo_to_device(slot, combos[slot].clone(), mode) {
+                    Ok(echoed) => {
+                        applied[slot] = echoed;
+                        changed += 1;
+                    }
+                    // a NAK is a single byte, so it shows up as the slot's echo timing out
+                    Err(e) if is_unanswered(&e) => {
+                        log::warn!("Key {} was not written, writing all: {}", slot + 1, e);
+                        let _ = self.serial_port.clear(ClearBuffer::Input);
+                        return self.send_combos_to_device(combos.clone(), mode);
+                    }
+                    Err(e) => return Err(e),
+                },
+            }
+        }
+        log::info!("{} of {} keys changed", changed, combos.len());
+        Ok(applied)
+    }
+
+    pub fn send_combo_to_device(
+        &mut self,
+        slot: usize,
+        combo: Option<KeyCombo>,
+        mode: ApplyMode,
+    ) -> Result<Option<KeyCombo>, KeypadError> {
+        self.require_slots("single key writes")?;
+        check_slot(slot)?;
+        self.with_retries(|keypad| {
+            let echoed = keypad.write_combo(slot, &combo, mode)?;
+            match echoed == combo {
+                true => Ok(echoed),
+                false => Err(KeypadError::VerifyFailed(ComboDiff {
+                    slots: vec![SlotDiff {
+                        slot,
+                        sent: combo.clone(),
+                        echoed,
+                    }],
+                })),
+            }
+        })
+    }
+
+    pub fn get_combo_from_device(&mut self, slot: usize) -> Result<Option<KeyCombo>, KeypadError> {
+        self.require_slots("single key reads")?;
+        check_slot(slot)?;
+        log::info!("Sending READ_KEY command for key {}...", slot + 1);
+        self.serial_port.write(&[READ_KEY, slot as u8])?;
+        self.serial_port.flush()?;
+
+        read_combo_from_port(&mut *self.serial_port, slot)
+    }
+
+    fn with_retries<T>(
+        &mut self,
+        mut operation: impl FnMut(&mut Self) -> Result<T, KeypadError>,
+    ) -> Result<T, KeypadError> {
+        let attempts = self.retry_policy.attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            match operation(self) {
+                Err(e) if attempt < attempts && is_retryable(&e) => {
+                    log::warn!("Write attempt {} of {} failed: {}", attempt, attempts, e);
+                    attempt += 1;
+                    sleep(self.retry_policy.delay);
+                    // drop whatever is left of a partial response
+                    let _ = self.serial_port.clear(ClearBuffer::Input);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn require_slots(&self, feature: &'static str) -> Result<(), KeypadError> {
+        match self.protocol {
+            Protocol::Slots => Ok(()),
+            Protocol::Legacy => Err(KeypadError::UnsupportedByFirmware(feature)),
+        }
+    }
+
+    // None when nothing has been applied since the EEPROM was erased,
+    // or when the firmware doesn't support profile IDs
+    pub fn get_profile_id(&mut self) -> Result<Option<Uuid>, KeypadError> {
+        if self.protocol == Protocol::Legacy {
+            return Ok(None);
+        }
+        log::info!("Sending READ_PROFILE_ID command...");
+        self.serial_port.write(&[READ_PROFILE_ID])?;
+        self.serial_port.flush()?;
+
+        let mut resp = [0u8; 16];
+        self.serial_port.read_exact(&mut resp)?;
+        match resp == [0u8; 16] || resp == [0xFFu8; 16] {
+            true => Ok(None),
+            false => Ok(Some(Uuid::from_bytes(resp))),
+        }
+    }
+
+    pub fn set_profile_id(&mut self, id: Uuid) -> Result<(), KeypadError> {
+        self.require_slots("profile IDs")?;
+        log::info!("Sending WRITE_PROFILE_ID command...");
+        let mut buf = vec![WRITE_PROFILE_ID];
+        buf.extend_from_slice(id.as_bytes());
+        self.serial_port.write(&buf)?;
+        self.serial_port.flush()?;
+        wait_for_acknowledge(&mut *self.serial_port)
+    }
+
+    // None when the firmware can't say which keys it can send
+    pub fn get_max_key(&mut self) -> Result<Option<Key>, KeypadError> {
+        if self.protocol == Protocol::Legacy {
+            return Ok(None);
+        }
+        log::info!("Sending READ_MAX_KEY command...");
+        self.serial_port.write(&[READ_MAX_KEY])?;
+        self.serial_port.flush()?;
+
+        let mut resp = [0u8; 2];
+        match self.serial_port.read_exact(&mut resp) {
+            Ok(()) => Key::from_u16(u16::from_le_bytes(resp))
+                .map(Some)
+                .ok_or(KeypadError::InvalidDataError),
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                log::info!("No max key returned.");
+                let _ = self.serial_port.clear(ClearBuffer::Input);
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // stores whatever the device is currently using to EEPROM
+    pub fn persist_combos(&mut self) -> Result<(), KeypadError> {
+        self.require_slots("storing loaded combos")?;
+        log::info!("Sending PERSIST command...");
+        self.serial_port.write(&[PERSIST])?;
+        self.serial_port.flush()?;
+        wait_for_acknowledge(&mut *self.serial_port)
+    }
+
+    fn write_combos(
+        &mut self,
+        combos: &[Option<KeyCombo>; MAX_KEYS],
+        mode: ApplyMode,
+    ) -> Result<[Option<KeyCombo>; MAX_KEYS], KeypadError> {
         log::info!("Loading combos into buffer...");
-        let mut buf = [0u8; 49];
-        buf[0] = WRITE_KEYS;
+        let mut buf = [0u8; 1 + MAX_KEYS * 8];
+        buf[0] = match (mode, self.protocol) {
+            (ApplyMode::Volatile, Protocol::Slots) => LOAD_KEYS,
+            (ApplyMode::Volatile, Protocol::Legacy) => {
+                log::warn!("Keypad firmware can't load combos without storing them");
+                WRITE_KEYS
+            }
+            (ApplyMode::Persistent, _) => WRITE_KEYS,
+        };
 
         let mut idx = 1;
 
         for combo in combos.iter() {
-            let bytes: Vec<u8> = combo.to_bytes();
+            let bytes: Vec<u8> = binding_bytes(combo);
             for b in bytes {
                 buf[idx] = b;
                 idx += 1;
             }
         }
 
-        log::info!("Sending WRITE_KEYS command...");
+        log::info!("Sending {} command...", buf[0] as char);
 
         self.serial_port.write(&buf)?;
         self.serial_port.flush()?;
 
-        read_combos_from_port(&mut *self.serial_port)
+        all_valid(read_combos_from_port(&mut *self.serial_port)?)
+    }
+
+    fn write_combo(
+        &mut self,
+        slot: usize,
+        combo: &Option<KeyCombo>,
+        mode: ApplyMode,
+    ) -> Result<Option<KeyCombo>, KeypadError> {
+        let command = match mode {
+            ApplyMode::Volatile => LOAD_KEY,
+            ApplyMode::Persistent => WRITE_KEY,
+        };
+        let mut buf = vec![command, slot as u8];
+        buf.extend(binding_bytes(combo));
+
+        log::info!("Sending {} for key {}...", command as char, slot + 1);
+        self.serial_port.write(&buf)?;
+        self.serial_port.flush()?;
+
+        read_combo_from_port(&mut *self.serial_port, slot)
     }
 
-    pub fn get_combos_from_device(&mut self) -> Result<[KeyCombo; 6], KeypadError> {
+    // fails on the first slot that can't be decoded, see read_combos_from_device
+    pub fn get_combos_from_device(&mut self) -> Result<[Option<KeyCombo>; MAX_KEYS], KeypadError> {
+        all_valid(self.read_combos_from_device()?)
+    }
+
+    pub fn read_combos_from_device(&mut self) -> Result<SlotCombos, KeypadError> {
         log::info!("Sending READ_KEYS command...");
         self.serial_port.write(&[READ_KEYS])?;
         self.serial_port.flush()?;
@@ -91,7 +472,7 @@ impl Keypad {
         read_combos_from_port(&mut *self.serial_port)
     }
 
-    pub fn flash_keys(&mut self, flash: [bool; 6]) -> Result<(), KeypadError> {
+    pub fn flash_keys(&mut self, flash: [bool; MAX_KEYS]) -> Result<(), KeypadError> {
         let mut mask = 0u8;
         for (idx, &on) in flash.iter().enumerate() {
             if on {
@@ -107,21 +488,54 @@ impl Keypad {
     }
 }
 
-fn handshake(port: &mut dyn SerialPort) -> Result<(), KeypadError> {
+fn is_retryable(error: &KeypadError) -> bool {
+    match error {
+        KeypadError::SerialCommunicationError(_)
+        | KeypadError::InvalidDataError
+        | KeypadError::InvalidSlotData(_)
+        | KeypadError::VerifyFailed(_) => true,
+        _ => false,
+    }
+}
+
+fn is_unanswered(error: &KeypadError) -> bool {
+    match error {
+        KeypadError::SerialCommunicationError(e) => e.kind() == std::io::ErrorKind::TimedOut,
+        KeypadError::NoAcknowledge => true,
+        _ => false,
+    }
+}
+
+fn check_slot(slot: usize) -> Result<(), KeypadError> {
+    match slot < MAX_KEYS {
+        true => Ok(()),
+        false => Err(KeypadError::InvalidSlot(slot)),
+    }
+}
+
+fn handshake(port: &mut dyn SerialPort) -> Result<Protocol, KeypadError> {
     let name = port.name().unwrap_or(String::new());
     log::info!("Sending handshake to {}", name);
     port.write(&[HELLO])?;
     port.flush()?;
 
-    match wait_for_acknowledge(port) {
-        Ok(()) => {
-            log::info!("Handshake returned! Keypad at {}", name);
-            Ok(())
-        }
-        Err(e) => {
-            log::info!("No handshake.");
-            Err(e)
-        }
+    if let Err(e) = wait_for_acknowledge(port) {
+        log::info!("No handshake.");
+        return Err(e);
+    }
+    log::info!("Handshake returned! Keypad at {}", name);
+    let protocol = read_protocol(port)?;
+    log::info!("Keypad protocol: {:?}", protocol);
+    Ok(protocol)
+}
+
+// only firmware with the slot commands follows the ACK with a protocol version
+fn read_protocol(port: &mut dyn SerialPort) -> Result<Protocol, KeypadError> {
+    let mut version = [0u8; 1];
+    match port.read_exact(&mut version) {
+        Ok(()) => Ok(Protocol::Slots),
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(Protocol::Legacy),
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -185,31 +599,25 @@ fn optional_key_bytes(option: &Option<KeyPress>) -> [u8; 4] {
 }
 
 impl KeyCombo {
-    fn from_bytes(bytes: &[u8]) -> Result<Self, KeypadError> {
-        let keys: Result<Vec<_>, _> = bytes
-            .chunks(2)
-            .map(|chunk| {
-                TryInto::<[u8; 2]>::try_into(chunk)
-                    .map(u16::from_le_bytes)
-                    .map_err(|_| KeypadError::InvalidDataError)
-            })
+    fn from_bytes(slot: usize, bytes: [u8; 8]) -> Result<Self, SlotError> {
+        let error = |kind| SlotError { slot, bytes, kind };
+        let words: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
             .collect();
+        if words.iter().all(|&w| w == 0x0000 || w == 0xFFFF) {
+            return Err(error(SlotErrorKind::Unconfigured));
+        }
 
-        let mut keys = keys?.into_iter();
-        let modifier = keys.next().unwrap();
-        let key = keys.next().unwrap();
-        let one = KeyPress::from_u16(modifier, key)?;
-
-        let modifier = keys.next().unwrap();
-        let key = keys.next().unwrap();
-        let two = if key > 0 {
-            Some(KeyPress::from_u16(modifier, key)?)
-        } else {
-            None
+        let one = KeyPress::from_u16(words[0], words[1]);
+        let two = match words[3] {
+            0 => Ok(None),
+            key => KeyPress::from_u16(words[2], key).map(Some),
         };
-
-        let combo = KeyCombo { one, two };
-        Ok(combo)
+        match (one, two) {
+            (Ok(one), Ok(two)) => Ok(KeyCombo { one, two }),
+            _ => Err(error(SlotErrorKind::Corrupt)),
+        }
     }
 
     fn to_bytes(&self) -> Vec<u8> {
@@ -221,27 +629,122 @@ impl KeyCombo {
     }
 }
 
-fn read_combos_from_port(port: &mut dyn SerialPort) -> Result<[KeyCombo; 6], KeypadError> {
+// a slot of all zeroes is a key with nothing bound to it
+fn decode_binding(slot: usize, bytes: [u8; 8]) -> Result<Option<KeyCombo>, SlotError> {
+    if bytes.iter().all(|&b| b == 0) {
+        return Ok(None);
+    }
+    KeyCombo::from_bytes(slot, bytes).map(Some)
+}
+
+fn binding_bytes(binding: &Option<KeyCombo>) -> Vec<u8> {
+    match binding {
+        Some(combo) => combo.to_bytes(),
+        None => vec![0u8; 8],
+    }
+}
+
+fn read_combos_from_port(port: &mut dyn SerialPort) -> Result<SlotCombos, KeypadError> {
     log::info!("Reading combos response into buffer...");
-    let mut resp: [u8; 48] = [0; 48];
+    let mut resp = [0u8; MAX_KEYS * 8];
     port.read_exact(&mut resp)?;
 
     log::info!("Parsing response into KeyCombos...");
-    let combos: Result<Vec<KeyCombo>, _> = resp
-        .chunks(8)
-        .map(|chunk| KeyCombo::from_bytes(chunk))
+    let combos = decode_combos(&resp)?;
+    for error in combos.iter().filter_map(|c| c.as_ref().err()) {
+        log::warn!("Error parsing combos: {}", error);
+    }
+    Ok(combos)
+}
+
+fn decode_combos(resp: &[u8]) -> Result<SlotCombos, KeypadError> {
+    let combos: Vec<_> = resp
+        .chunks_exact(8)
+        .enumerate()
+        .map(|(slot, chunk)| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(chunk);
+            decode_binding(slot, bytes)
+        })
         .collect();
+    combos
+        .try_into()
+        .map_err(|_| KeypadError::WrongKeyCountFromDevice)
+}
 
-    match combos {
-        Ok(combos) => {
-            log::info!("Parsed {} combos", combos.len());
-            combos
-                .try_into()
-                .map_err(|_| KeypadError::WrongKeyCountFromDevice)
-        }
-        Err(e) => {
-            log::error!("Error parsing combos: {}", e);
-            Err(e)
+fn all_valid(combos: SlotCombos) -> Result<[Option<KeyCombo>; MAX_KEYS], KeypadError> {
+    let combos: Result<Vec<Option<KeyCombo>>, SlotError> = combos.iter().cloned().collect();
+    combos?
+        .try_into()
+        .map_err(|_| KeypadError::WrongKeyCountFromDevice)
+}
+
+fn read_combo_from_port(
+    port: &mut dyn SerialPort,
+    slot: usize,
+) -> Result<Option<KeyCombo>, KeypadError> {
+    let mut resp = [0u8; 8];
+    port.read_exact(&mut resp)?;
+    Ok(decode_binding(slot, resp)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_binding;
+
+    fn combos(keys: [&str; 6]) -> [Option<KeyCombo>; 6] {
+        let combos: Vec<_> = keys.iter().map(|k| parse_binding(k).unwrap()).collect();
+        combos.try_into().unwrap()
+    }
+
+    #[test]
+    fn diff_lists_mismatched_slots() {
+        let sent = combos(["A", "B", "Ctrl+C", "D", "E", "Ctrl+K, Ctrl+C"]);
+        let echoed = combos(["A", "B", "Ctrl+V", "D", "E", "None"]);
+
+        let diff = ComboDiff::between(&sent, &echoed);
+        assert_eq!(
+            diff.slots.iter().map(|d| d.slot).collect::<Vec<_>>(),
+            vec![2, 5]
+        );
+        assert_eq!(diff.slots[0].sent, sent[2]);
+        assert_eq!(diff.slots[0].echoed, echoed[2]);
+        assert_eq!(
+            diff.to_string(),
+            "key 3: sent Ctrl + C, device has Ctrl + V; key 6: sent Ctrl + K, Ctrl + C, device has None"
+        );
+        assert!(ComboDiff::between(&sent, &sent).is_empty());
+    }
+
+    #[test]
+    fn blank_and_garbage_slots_are_reported() {
+        let valid = combos(["A", "B", "C", "None", "E", "F"]);
+        let mut resp: Vec<u8> = valid.iter().flat_map(binding_bytes).collect();
+        resp[0..8].copy_from_slice(&[0xFF; 8]);
+        resp[8..16].copy_from_slice(&[0xFF, 0xFF, 0, 0, 0xFF, 0xFF, 0, 0]);
+        resp[16..24].copy_from_slice(&[0, 0, 0x12, 0x34, 0, 0, 0, 0]);
+
+        let decoded = decode_combos(&resp).unwrap();
+        assert_eq!(
+            decoded[0].as_ref().unwrap_err().kind,
+            SlotErrorKind::Unconfigured
+        );
+        assert_eq!(
+            decoded[1].as_ref().unwrap_err().kind,
+            SlotErrorKind::Unconfigured
+        );
+        let corrupt = decoded[2].as_ref().unwrap_err();
+        assert_eq!(corrupt.kind, SlotErrorKind::Corrupt);
+        assert_eq!(
+            corrupt.to_string(),
+            "key 3 has invalid data (00 00 12 34 00 00 00 00)"
+        );
+        assert_eq!(decoded[3], Ok(None));
+        for slot in 3..6 {
+            assert_eq!(decoded[slot], Ok(valid[slot].clone()));
         }
+        assert!(all_valid(decoded).is_err());
+        assert!(decode_combos(&resp[..40]).is_err());
     }
 }
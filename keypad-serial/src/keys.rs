@@ -1,7 +1,23 @@
-use std::fmt::{Display, Formatter};
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
 
 use enum_primitive::*;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum ParseComboError {
+    #[error("Key combo is empty")]
+    Empty,
+    #[error("Key combo has more than two key presses")]
+    TooManyPresses,
+    #[error("Unknown key: {0}")]
+    UnknownKey(String),
+    #[error("Unknown modifier: {0}")]
+    UnknownModifier(String),
+}
 
 #[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
 pub struct KeyCombo {
@@ -19,15 +35,71 @@ impl Display for KeyCombo {
     }
 }
 
-impl Default for KeyCombo {
-    fn default() -> Self {
-        Self {
-            one: KeyPress::key(Key::A),
-            two: None,
+impl FromStr for KeyCombo {
+    type Err = ParseComboError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut presses = s.split(',');
+        let one = presses.next().unwrap_or_default().parse()?;
+        let two = match presses.next() {
+            Some(press) => Some(press.parse()?),
+            None => None,
+        };
+        if presses.next().is_some() {
+            return Err(ParseComboError::TooManyPresses);
         }
+        Ok(KeyCombo { one, two })
+    }
+}
+
+// Text form of a key with nothing bound to it
+pub const UNASSIGNED: &str = "None";
+
+pub fn binding_to_string(binding: Option<&KeyCombo>) -> String {
+    match binding {
+        Some(combo) => combo.to_string(),
+        None => UNASSIGNED.into(),
+    }
+}
+
+pub fn parse_binding(s: &str) -> Result<Option<KeyCombo>, ParseComboError> {
+    match s.trim() {
+        text if text.eq_ignore_ascii_case(UNASSIGNED) => Ok(None),
+        text => text.parse().map(Some),
+    }
+}
+
+// what a key is for, the icon is a name or path the UI can look up
+#[derive(Deserialize, Serialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct KeyLabel {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
+
+impl KeyLabel {
+    pub fn is_empty(&self) -> bool {
+        self.label.is_none() && self.description.is_none() && self.icon.is_none()
     }
 }
 
+// e.g. "Copy (Ctrl + C) - copy the selection", or just the combo without a label
+pub fn describe_binding(binding: Option<&KeyCombo>, label: &KeyLabel) -> String {
+    let combo = binding_to_string(binding);
+    let mut text = match &label.label {
+        Some(label) => format!("{} ({})", label, combo),
+        None => combo,
+    };
+    if let Some(description) = &label.description {
+        text.push_str(" - ");
+        text.push_str(description);
+    }
+    text
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
 pub struct KeyPress {
     pub ctrl: bool,
@@ -135,6 +207,30 @@ impl Display for KeyPress {
     }
 }
 
+impl FromStr for KeyPress {
+    type Err = ParseComboError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = match parts.pop() {
+            Some(key) if key.len() > 0 => key.parse()?,
+            _ => return Err(ParseComboError::Empty),
+        };
+
+        let mut press = KeyPress::key(key);
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_ref() {
+                "ctrl" | "control" => press.ctrl = true,
+                "alt" => press.alt = true,
+                "shift" => press.shift = true,
+                "win" | "windows" => press.windows = true,
+                _ => return Err(ParseComboError::UnknownModifier(modifier.into())),
+            }
+        }
+        Ok(press)
+    }
+}
+
 enum_from_primitive! {
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ModifierKey {
@@ -399,6 +495,19 @@ impl Display for Key {
     }
 }
 
+impl FromStr for Key {
+    type Err = ParseComboError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim();
+        Key::ALL
+            .iter()
+            .find(|k| k.to_string().eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| ParseComboError::UnknownKey(name.into()))
+    }
+}
+
 impl Key {
     pub const ALL: [Key; 110] = [
         Key::A,
@@ -513,3 +622,50 @@ impl Key {
         Key::F24,
     ];
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combos_round_trip_through_display() {
+        let texts = [
+            "A",
+            "Ctrl + Shift + F5",
+            "Ctrl + Alt + Shift + Win + Delete, Enter",
+        ];
+        for text in texts.iter() {
+            let combo: KeyCombo = text.parse().unwrap();
+            assert_eq!(combo.to_string(), *text);
+        }
+        for key in Key::ALL.iter() {
+            assert_eq!(key.to_string().parse::<Key>(), Ok(*key));
+        }
+
+        let combo: KeyCombo = " win+control + keypad1 ,tab".parse().unwrap();
+        assert_eq!(combo.to_string(), "Ctrl + Win + KeyPad1, Tab");
+        assert_eq!(parse_binding("none"), Ok(None));
+        assert_eq!(binding_to_string(None), UNASSIGNED);
+    }
+
+    #[test]
+    fn invalid_combos_are_rejected() {
+        let error = |text: &str| text.parse::<KeyCombo>().unwrap_err();
+        assert_eq!(error(""), ParseComboError::Empty);
+        assert_eq!(error("Ctrl +"), ParseComboError::Empty);
+        assert_eq!(error("A,"), ParseComboError::Empty);
+        assert_eq!(error("A, B, C"), ParseComboError::TooManyPresses);
+        assert_eq!(
+            error("Ctrl + Foo"),
+            ParseComboError::UnknownKey("Foo".into())
+        );
+        assert_eq!(
+            error("Hyper + A"),
+            ParseComboError::UnknownModifier("Hyper".into())
+        );
+        assert_eq!(
+            parse_binding("Nothing"),
+            Err(ParseComboError::UnknownKey("Nothing".into()))
+        );
+    }
+}
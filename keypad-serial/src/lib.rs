@@ -1,5 +1,13 @@
+mod board;
+mod cheat_sheet;
 mod keypad;
 mod keys;
+mod lint;
+mod profile;
 
+pub use board::*;
+pub use cheat_sheet::*;
 pub use keypad::*;
 pub use keys::*;
+pub use lint::*;
+pub use profile::*;
@@ -0,0 +1,179 @@
+use std::fmt::{Display, Formatter};
+
+use lazy_static::lazy_static;
+
+use super::{
+    board::Board,
+    keys::{Key, KeyCombo, KeyPress},
+};
+
+lazy_static! {
+    // shortcuts Windows handles itself, or that do something drastic to whatever has focus
+    static ref RESERVED: Vec<(KeyPress, &'static str)> = [
+        ("Win + L", "locks the computer"),
+        ("Ctrl + Alt + Delete", "opens the security screen"),
+        ("Ctrl + Shift + Esc", "opens Task Manager"),
+        ("Ctrl + Esc", "opens the Start menu"),
+        ("Alt + Tab", "switches windows"),
+        ("Alt + F4", "closes the active window"),
+    ]
+    .iter()
+    .map(|(press, action)| (press.parse().expect("Invalid reserved shortcut"), *action))
+    .collect();
+}
+
+// Key has no modifier keys, so every press already has a non-modifier key,
+// but a combo of only these still does nothing on its own
+const LOCK_KEYS: [Key; 3] = [Key::CapsLock, Key::NumLock, Key::ScrollLock];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LintWarning {
+    DuplicateCombo {
+        slot: usize,
+        first: usize,
+    },
+    ReservedShortcut {
+        slot: usize,
+        press: KeyPress,
+        action: &'static str,
+    },
+    OnlyLockKeys {
+        slot: usize,
+    },
+    UnsupportedKey {
+        slot: usize,
+        key: Key,
+    },
+    NotOnBoard {
+        slot: usize,
+    },
+}
+
+impl LintWarning {
+    pub fn slot(&self) -> usize {
+        match self {
+            LintWarning::DuplicateCombo { slot, .. }
+            | LintWarning::ReservedShortcut { slot, .. }
+            | LintWarning::OnlyLockKeys { slot }
+            | LintWarning::UnsupportedKey { slot, .. }
+            | LintWarning::NotOnBoard { slot } => *slot,
+        }
+    }
+}
+
+impl Display for LintWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "key {} ", self.slot() + 1)?;
+        match self {
+            LintWarning::DuplicateCombo { first, .. } => {
+                write!(f, "does the same as key {}", first + 1)
+            }
+            LintWarning::ReservedShortcut { press, action, .. } => {
+                write!(f, "presses {}, which {}", press, action)
+            }
+            LintWarning::OnlyLockKeys { .. } => write!(f, "only presses lock keys"),
+            LintWarning::UnsupportedKey { key, .. } => {
+                write!(f, "uses {}, which the keypad's firmware can't send", key)
+            }
+            LintWarning::NotOnBoard { .. } => write!(f, "isn't on the board"),
+        }
+    }
+}
+
+// max_key is the highest key the connected keypad can send, None skips that check
+pub fn lint_combos(
+    combos: &[Option<KeyCombo>],
+    board: &Board,
+    max_key: Option<Key>,
+) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+    for (slot, combo) in combos.iter().enumerate() {
+        let combo = match combo {
+            Some(combo) => combo,
+            None => continue,
+        };
+        if slot >= board.key_count() {
+            warnings.push(LintWarning::NotOnBoard { slot });
+            continue;
+        }
+        let presses: Vec<&KeyPress> = Some(&combo.one).into_iter().chain(&combo.two).collect();
+
+        if let Some(first) = combos[..slot]
+            .iter()
+            .position(|c| c.as_ref() == Some(combo))
+        {
+            warnings.push(LintWarning::DuplicateCombo { slot, first });
+        }
+        for press in presses.iter() {
+            if let Some((_, action)) = RESERVED.iter().find(|(r, _)| r == *press) {
+                warnings.push(LintWarning::ReservedShortcut {
+                    slot,
+                    press: (*press).clone(),
+                    action: *action,
+                });
+            }
+        }
+        if presses.iter().all(|p| LOCK_KEYS.contains(&p.key)) {
+            warnings.push(LintWarning::OnlyLockKeys { slot });
+        }
+        if let Some(max_key) = max_key {
+            for press in presses.iter().filter(|p| p.key as u16 > max_key as u16) {
+                warnings.push(LintWarning::UnsupportedKey {
+                    slot,
+                    key: press.key,
+                });
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_binding;
+
+    fn combos(keys: [&str; 6]) -> Vec<Option<KeyCombo>> {
+        keys.iter().map(|k| parse_binding(k).unwrap()).collect()
+    }
+
+    #[test]
+    fn problem_combos_are_reported() {
+        let combos = combos([
+            "Ctrl + C",
+            "Ctrl + C",
+            "Ctrl + A, Win + L",
+            "CapsLock",
+            "F13",
+            "None",
+        ]);
+        let warnings = lint_combos(&combos, &Board::default(), Some(Key::Menu));
+        let expected = vec![
+            LintWarning::DuplicateCombo { slot: 1, first: 0 },
+            LintWarning::ReservedShortcut {
+                slot: 2,
+                press: "Win + L".parse().unwrap(),
+                action: "locks the computer",
+            },
+            LintWarning::OnlyLockKeys { slot: 3 },
+            LintWarning::UnsupportedKey {
+                slot: 4,
+                key: Key::F13,
+            },
+        ];
+        assert_eq!(warnings, expected);
+        assert_eq!(
+            warnings[1].to_string(),
+            "key 3 presses Win + L, which locks the computer"
+        );
+
+        assert!(lint_combos(&combos[4..], &Board::default(), None).is_empty());
+
+        let mut board = Board::default();
+        board.keys.truncate(1);
+        assert_eq!(
+            lint_combos(&combos[..2], &board, None),
+            [LintWarning::NotOnBoard { slot: 1 }]
+        );
+    }
+}
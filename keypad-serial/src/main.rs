@@ -1,19 +1,125 @@
-use std::env;
+use std::{
+    env,
+    error::Error,
+    fs::{read_to_string, write},
+    path::Path,
+};
 
 use keypad::*;
+use serde::Deserialize;
+use serde_json::{from_str, from_value, Value};
+use uuid::Uuid;
 
-fn main() -> Result<(), KeypadError> {
+const CHEAT_SHEET_USAGE: &str = "Usage: keypad-test cheat-sheet <profiles.json> [sheet.svg]";
+
+#[derive(Deserialize)]
+struct ProfilesFile {
+    profiles: Vec<StoredProfile>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
     if let Err(_) = env::var("RUST_LOG") {
         env::set_var("RUST_LOG", "info");
     }
     pretty_env_logger::init();
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("cheat-sheet") {
+        return write_cheat_sheet(args.get(1).ok_or(CHEAT_SHEET_USAGE)?, args.get(2));
+    }
+    // an optional path to the tray's profiles.json, used for key labels
+    let profiles_path = args.first().map(String::as_str);
+    let board = board_for(profiles_path)?;
+
     let mut keypad = Keypad::auto_detect()?;
 
-    let saved = keypad.get_combos_from_device()?;
-    for combo in saved.iter() {
-        println!("{}", combo);
+    let id = keypad.get_profile_id()?;
+    match id {
+        Some(id) => println!("Profile: {}", id),
+        None => println!("Profile: unknown"),
+    }
+
+    let labels = match (profiles_path, id) {
+        (Some(path), Some(id)) => labels_from_file(path, id)?.unwrap_or_else(|| {
+            println!("No labels found for this profile in {}", path);
+            Vec::new()
+        }),
+        _ => Vec::new(),
+    };
+
+    let saved = keypad.read_combos_from_device()?;
+    for (slot, (key, combo)) in board.keys.iter().zip(saved.iter()).enumerate() {
+        match combo {
+            Ok(combo) => {
+                let label = labels.get(slot).cloned().unwrap_or_default();
+                println!("{}: {}", key.name, describe_binding(combo.as_ref(), &label))
+            }
+            Err(e) => println!("{}: {}", key.name, e),
+        }
+    }
+
+    let combos: Vec<Option<KeyCombo>> = saved.iter().map(|c| c.clone().unwrap_or(None)).collect();
+    let max_key = keypad.get_max_key()?;
+    for warning in lint_combos(&combos, &board, max_key) {
+        println!("Warning: {}", warning);
     }
 
     Ok(())
 }
+
+// every profile in the file, a single page can go to stdout without an output path
+fn write_cheat_sheet(profiles_path: &str, out: Option<&String>) -> Result<(), Box<dyn Error>> {
+    let profiles = read_profiles(profiles_path)?;
+    let entries = profiles
+        .iter()
+        .map(|p| p.cheat_sheet_entry(&profiles))
+        .collect::<Result<Vec<_>, _>>()?;
+    let pages = render_cheat_sheet(&board_for(Some(profiles_path))?, &entries);
+    match out {
+        Some(out) => {
+            for (page, svg) in pages.iter().enumerate() {
+                write(cheat_sheet_page_path(Path::new(out), page), svg)?;
+            }
+        }
+        None if pages.len() == 1 => print!("{}", pages[0]),
+        None => return Err(format!("{} pages need an output path", pages.len()).into()),
+    }
+    Ok(())
+}
+
+// a board.json next to the profiles, the same place the tray looks for it,
+// without a profiles file there's nowhere to look so it's the standard board
+fn board_for(profiles_path: Option<&str>) -> Result<Board, BoardError> {
+    match profiles_path.and_then(|p| Path::new(p).parent()) {
+        Some(dir) => Board::locate(dir),
+        None => Ok(Board::default()),
+    }
+}
+
+fn labels_from_file(path: &str, id: Uuid) -> Result<Option<Vec<KeyLabel>>, Box<dyn Error>> {
+    let profiles = read_profiles(path)?;
+    match profiles.iter().find(|p| p.id == id) {
+        Some(profile) => Ok(Some(profile.resolve_labels(&profiles)?.to_vec())),
+        None => Ok(None),
+    }
+}
+
+// only JSON files at the current version, anything else has to be opened in the tray first
+fn read_profiles(path: &str) -> Result<Vec<StoredProfile>, Box<dyn Error>> {
+    let is_json = Path::new(path)
+        .extension()
+        .map_or(false, |ext| ext == "json");
+    if !is_json {
+        return Err(format!("{} isn't a JSON profiles file", path).into());
+    }
+    let document: Value = from_str(&read_to_string(path)?)?;
+    match document.get("version").and_then(Value::as_u64) {
+        Some(version) if version == PROFILES_VERSION as u64 => {}
+        _ => {
+            let message = format!("{} needs to be opened in the tray to upgrade it", path);
+            return Err(message.into());
+        }
+    }
+    let file: ProfilesFile = from_value(document)?;
+    Ok(file.profiles)
+}
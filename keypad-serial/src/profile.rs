@@ -0,0 +1,198 @@
+use serde::Deserialize;
+use thiserror::Error;
+use uuid::Uuid;
+
+use super::{
+    board::MAX_KEYS,
+    cheat_sheet::CheatSheetEntry,
+    keys::{KeyCombo, KeyLabel},
+};
+
+// the schema of the tray's profiles file, the tray upgrades older files when it loads them
+pub const PROFILES_VERSION: u32 = 3;
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum ProfileError {
+    #[error("Profile {0} inherits from a profile that doesn't exist")]
+    MissingParent(String),
+    #[error("Profile {0} inherits from itself")]
+    InheritanceCycle(String),
+    #[error("Profile {0} inherits keys but has no parent")]
+    NoParent(String),
+    #[error("Profiles {0} and {1} have the same id")]
+    DuplicateId(String, String),
+}
+
+// the parts of a profile that decide what its keys do, inheritance is resolved the same
+// way for the tray's profiles and for ones read straight from a profiles file
+pub trait ProfileKeys: Sized {
+    fn id(&self) -> Uuid;
+    fn name(&self) -> &str;
+    fn parent(&self) -> Option<Uuid>;
+    // keys taken from the parent, their own combos are ignored
+    fn inherited(&self) -> &[bool; MAX_KEYS];
+    fn combos(&self) -> &[Option<KeyCombo>; MAX_KEYS];
+    fn labels(&self) -> &[KeyLabel; MAX_KEYS];
+
+    // the combos to apply, with inherited keys filled in from the nearest ancestor that sets them
+    fn resolve(&self, profiles: &[Self]) -> Result<[Option<KeyCombo>; MAX_KEYS], ProfileError> {
+        let chain = self.chain(profiles)?;
+        let mut combos: [Option<KeyCombo>; MAX_KEYS] = Default::default();
+        for (slot, combo) in combos.iter_mut().enumerate() {
+            *combo = owner(&chain, slot).combos()[slot].clone();
+        }
+        Ok(combos)
+    }
+
+    fn resolve_labels(&self, profiles: &[Self]) -> Result<[KeyLabel; MAX_KEYS], ProfileError> {
+        let chain = self.chain(profiles)?;
+        let mut labels: [KeyLabel; MAX_KEYS] = Default::default();
+        for (slot, label) in labels.iter_mut().enumerate() {
+            *label = owner(&chain, slot).labels()[slot].clone();
+        }
+        Ok(labels)
+    }
+
+    fn cheat_sheet_entry(&self, profiles: &[Self]) -> Result<CheatSheetEntry, ProfileError> {
+        Ok(CheatSheetEntry {
+            name: self.name().into(),
+            combos: self.resolve(profiles)?.to_vec(),
+            labels: self.resolve_labels(profiles)?.to_vec(),
+        })
+    }
+
+    // this profile followed by its ancestors, nearest first
+    fn chain<'a>(&'a self, profiles: &'a [Self]) -> Result<Vec<&'a Self>, ProfileError> {
+        let mut chain = vec![self];
+        let mut profile = self;
+        while let Some(parent_id) = profile.parent() {
+            let parent = profiles
+                .iter()
+                .find(|p| p.id() == parent_id)
+                .ok_or_else(|| ProfileError::MissingParent(profile.name().into()))?;
+            if chain.iter().any(|p| p.id() == parent.id()) {
+                return Err(ProfileError::InheritanceCycle(self.name().into()));
+            }
+            chain.push(parent);
+            profile = parent;
+        }
+        if profile.inherited().contains(&true) {
+            return Err(ProfileError::NoParent(profile.name().into()));
+        }
+        Ok(chain)
+    }
+
+    // whether this is the profile with the given id or inherits from it, even through a broken chain
+    fn inherits_from(&self, id: Uuid, profiles: &[Self]) -> bool {
+        let mut profile = Some(self);
+        for _ in 0..=profiles.len() {
+            profile = match profile {
+                Some(p) if p.id() == id => return true,
+                Some(p) => p
+                    .parent()
+                    .and_then(|parent| profiles.iter().find(|c| c.id() == parent)),
+                None => return false,
+            };
+        }
+        false
+    }
+}
+
+// a profile as the tray saves it, without the rules for switching to it
+#[derive(Deserialize, Debug, Clone)]
+pub struct StoredProfile {
+    pub id: Uuid,
+    pub name: String,
+    pub parent: Option<Uuid>,
+    #[serde(default)]
+    pub inherited: [bool; MAX_KEYS],
+    pub combos: [Option<KeyCombo>; MAX_KEYS],
+    #[serde(default)]
+    pub labels: [KeyLabel; MAX_KEYS],
+}
+
+impl ProfileKeys for StoredProfile {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn parent(&self) -> Option<Uuid> {
+        self.parent
+    }
+
+    fn inherited(&self) -> &[bool; MAX_KEYS] {
+        &self.inherited
+    }
+
+    fn combos(&self) -> &[Option<KeyCombo>; MAX_KEYS] {
+        &self.combos
+    }
+
+    fn labels(&self) -> &[KeyLabel; MAX_KEYS] {
+        &self.labels
+    }
+}
+
+// the root of a valid chain never inherits, so there's always an owner
+fn owner<'a, P: ProfileKeys>(chain: &[&'a P], slot: usize) -> &'a P {
+    chain
+        .iter()
+        .find(|p| !p.inherited()[slot])
+        .copied()
+        .unwrap_or(chain[chain.len() - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_binding;
+    use serde_json::{from_value, json};
+
+    fn combos(keys: [&str; MAX_KEYS]) -> Vec<Option<KeyCombo>> {
+        keys.iter().map(|k| parse_binding(k).unwrap()).collect()
+    }
+
+    #[test]
+    fn stored_profiles_resolve_inherited_keys() {
+        let base = Uuid::from_u128(1);
+        let profiles: Vec<StoredProfile> = from_value(json!([
+            {
+                "id": base,
+                "name": "Default",
+                "parent": null,
+                "combos": combos(["A", "B", "C", "D", "E", "F"]),
+                "labels": [{}, {"label": "Bold"}, {}, {}, {}, {}],
+                "auto_switch": null
+            },
+            {
+                "id": Uuid::from_u128(2),
+                "name": "IDE",
+                "parent": base,
+                "inherited": [true, true, false, true, true, true],
+                "combos": combos(["None", "None", "F5", "None", "None", "None"]),
+                "auto_switch": null
+            }
+        ]))
+        .unwrap();
+
+        let entry = profiles[1].cheat_sheet_entry(&profiles).unwrap();
+        assert_eq!(entry.name, "IDE");
+        assert_eq!(entry.combos[1], Some("B".parse().unwrap()));
+        assert_eq!(entry.combos[2], Some("F5".parse().unwrap()));
+        assert_eq!(entry.labels[1].label.as_deref(), Some("Bold"));
+        assert!(profiles[1].inherits_from(base, &profiles));
+
+        let orphan = StoredProfile {
+            parent: Some(Uuid::from_u128(3)),
+            ..profiles[1].clone()
+        };
+        assert_eq!(
+            orphan.resolve(&profiles),
+            Err(ProfileError::MissingParent("IDE".into()))
+        );
+    }
+}
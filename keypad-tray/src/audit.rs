@@ -0,0 +1,264 @@
+use std::{
+    fmt::{Display, Formatter},
+    fs::{metadata, read_to_string, remove_file, rename, OpenOptions},
+    io::prelude::*,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use thiserror::Error;
+
+const AUDIT_FILE: &str = "audit";
+const AUDIT_EXTENSION: &str = "jsonl";
+const MAX_AUDIT_BYTES: u64 = 256 * 1024;
+const MAX_AUDIT_FILES: usize = 3;
+
+#[derive(Error, Debug)]
+pub enum AuditError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Serde JSON error: {0}")]
+    SerdeError(#[from] serde_json::error::Error),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SwitchReason {
+    Rules { rules: String },
+    Default,
+    Manual,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Switch {
+        profile: String,
+        reason: SwitchReason,
+    },
+    Write {
+        profile: String,
+    },
+    // profile is None when the device holds combos that match no profile
+    ReadBack {
+        profile: Option<String>,
+    },
+    Error {
+        operation: String,
+        message: String,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Entry {
+    pub time: DateTime<Local>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+// Appends one JSON object per line to audit.jsonl, older lines move to audit.1.jsonl and so on
+#[derive(Clone)]
+pub struct AuditLog {
+    dir: PathBuf,
+    lock: Arc<Mutex<()>>,
+}
+
+impl AuditLog {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.into(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    // failing to audit shouldn't fail the operation being audited
+    pub fn record(&self, event: Event) {
+        if let Err(e) = self.append(event) {
+            log::warn!("Unable to write audit log: {}", e);
+        }
+    }
+
+    pub fn error(&self, operation: &str, error: impl Display) {
+        self.record(Event::Error {
+            operation: operation.into(),
+            message: error.to_string(),
+        });
+    }
+
+    // the most recent entries, oldest first
+    pub fn recent(&self, count: usize) -> Result<Vec<Entry>, AuditError> {
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = Vec::new();
+        for idx in 0..MAX_AUDIT_FILES {
+            let path = self.file(idx);
+            if !path.exists() {
+                break;
+            }
+            let contents = read_to_string(&path)?;
+            // a line cut short by a crash, or from a newer version, shouldn't hide the rest
+            let mut lines: Vec<Entry> = contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .filter_map(|line| match from_str(line) {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        log::warn!("Skipping audit entry in {}: {}", path.display(), e);
+                        None
+                    }
+                })
+                .collect();
+            lines.append(&mut entries);
+            entries = lines;
+            if entries.len() >= count {
+                break;
+            }
+        }
+        let skip = entries.len().saturating_sub(count);
+        Ok(entries.split_off(skip))
+    }
+
+    fn append(&self, event: Event) -> Result<(), AuditError> {
+        let line = to_string(&Entry {
+            time: Local::now(),
+            event,
+        })?;
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.rotate_if_full()?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.file(0))?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+
+    fn rotate_if_full(&self) -> Result<(), AuditError> {
+        match metadata(self.file(0)) {
+            Ok(meta) if meta.len() >= MAX_AUDIT_BYTES => {}
+            _ => return Ok(()),
+        }
+        let oldest = self.file(MAX_AUDIT_FILES - 1);
+        if oldest.exists() {
+            remove_file(oldest)?;
+        }
+        for idx in (0..MAX_AUDIT_FILES - 1).rev() {
+            let path = self.file(idx);
+            if path.exists() {
+                rename(path, self.file(idx + 1))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn file(&self, idx: usize) -> PathBuf {
+        match idx {
+            0 => self.dir.join(format!("{}.{}", AUDIT_FILE, AUDIT_EXTENSION)),
+            idx => self
+                .dir
+                .join(format!("{}.{}.{}", AUDIT_FILE, idx, AUDIT_EXTENSION)),
+        }
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ", self.time.format("%Y-%m-%d %H:%M:%S"))?;
+        match &self.event {
+            Event::Switch { profile, reason } => match reason {
+                SwitchReason::Rules { rules } => {
+                    write!(f, "Switched to {} ({})", profile, rules)
+                }
+                SwitchReason::Default => write!(f, "Switched back to {}", profile),
+                SwitchReason::Manual => write!(f, "Selected {}", profile),
+            },
+            Event::Write { profile } => write!(f, "Wrote {} to keypad", profile),
+            Event::ReadBack {
+                profile: Some(profile),
+            } => {
+                write!(f, "Keypad has {}", profile)
+            }
+            Event::ReadBack { profile: None } => write!(f, "Keypad has unknown combos"),
+            Event::Error { operation, message } => {
+                write!(f, "Error during {}: {}", operation, message)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn test_log(name: &str) -> AuditLog {
+        let dir = env::temp_dir().join(format!("keypad-audit-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        AuditLog::new(&dir)
+    }
+
+    fn write(profile: &str) -> Event {
+        Event::Write {
+            profile: profile.into(),
+        }
+    }
+
+    #[test]
+    fn recent_returns_latest_entries_in_order() {
+        let log = test_log("recent");
+        log.record(Event::Switch {
+            profile: "Photoshop".into(),
+            reason: SwitchReason::Rules {
+                rules: "photoshop.exe".into(),
+            },
+        });
+        log.record(write("Photoshop"));
+        log.error("write", "Unable to find keypad");
+
+        let events: Vec<Event> = log
+            .recent(2)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.event)
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                write("Photoshop"),
+                Event::Error {
+                    operation: "write".into(),
+                    message: "Unable to find keypad".into(),
+                }
+            ]
+        );
+        assert_eq!(log.recent(10).unwrap().len(), 3);
+
+        let mut file = OpenOptions::new().append(true).open(log.file(0)).unwrap();
+        writeln!(file, "{{\"time\": \"2021-03-01").unwrap();
+        log.record(write("Default"));
+        let recent = log.recent(10).unwrap();
+        assert_eq!(recent.len(), 4);
+        assert_eq!(recent.last().unwrap().event, write("Default"));
+    }
+
+    #[test]
+    fn full_log_is_rotated() {
+        let log = test_log("rotate");
+        let name = "x".repeat(1024);
+        let per_file = (MAX_AUDIT_BYTES / 1024) as usize + 1;
+        for _ in 0..per_file * (MAX_AUDIT_FILES + 1) {
+            log.record(write(&name));
+        }
+        log.record(write("last"));
+
+        assert!(log.file(MAX_AUDIT_FILES - 1).exists());
+        assert!(!log.file(MAX_AUDIT_FILES).exists());
+        let recent = log.recent(per_file).unwrap();
+        assert_eq!(recent.len(), per_file);
+        assert_eq!(recent.last().unwrap().event, write("last"));
+    }
+}
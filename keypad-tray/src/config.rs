@@ -0,0 +1,107 @@
+use std::{env, fs::read_to_string, path::PathBuf, time::Duration};
+
+use serde::Deserialize;
+use serde_json::from_str;
+use thiserror::Error;
+
+const CONFIG_FILE: &str = "keypad.json";
+const POLL_INTERVAL_FLAG: &str = "--poll-interval-ms";
+const POLL_INTERVAL_VAR: &str = "KEYPAD_POLL_INTERVAL_MS";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Serde JSON error: {0}")]
+    SerdeError(#[from] serde_json::error::Error),
+    #[error("Invalid poll interval, expected milliseconds: {0}")]
+    InvalidPollInterval(String),
+}
+
+// keypad.json next to the executable
+#[derive(Deserialize)]
+struct Config {
+    profiles_dir: Option<PathBuf>,
+    poll_interval_ms: Option<u64>,
+}
+
+pub fn profiles_dir_setting() -> Result<Option<PathBuf>, ConfigError> {
+    let (exe_dir, config) = match read_config()? {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+    // relative paths are resolved against the executable, e.g. "." for a portable install
+    Ok(config.profiles_dir.map(|dir| exe_dir.join(dir)))
+}
+
+// Only used when process events aren't available. Looked up like the profiles directory:
+// --poll-interval-ms, then KEYPAD_POLL_INTERVAL_MS, then poll_interval_ms in keypad.json.
+pub fn poll_interval_setting() -> Result<Option<Duration>, ConfigError> {
+    let millis = match value_from_args(env::args(), POLL_INTERVAL_FLAG) {
+        Some(millis) => Some(millis),
+        None => env::var(POLL_INTERVAL_VAR).ok(),
+    };
+    let millis = match millis {
+        Some(millis) => Some(
+            millis
+                .trim()
+                .parse()
+                .map_err(|_| ConfigError::InvalidPollInterval(millis))?,
+        ),
+        None => read_config()?.and_then(|(_, config)| config.poll_interval_ms),
+    };
+    Ok(millis.map(Duration::from_millis))
+}
+
+pub fn value_from_args<I: Iterator<Item = String>>(mut args: I, flag: &str) -> Option<String> {
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix(flag).and_then(|a| a.strip_prefix('=')) {
+            return Some(value.into());
+        }
+    }
+    None
+}
+
+fn read_config() -> Result<Option<(PathBuf, Config)>, ConfigError> {
+    let exe = env::current_exe()?;
+    let exe_dir = match exe.parent() {
+        Some(dir) => dir,
+        None => return Ok(None),
+    };
+    let path = exe_dir.join(CONFIG_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let config: Config = from_str(&read_to_string(&path)?)?;
+    Ok(Some((exe_dir.into(), config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_interval_is_read_from_args() {
+        let args = |a: &[&str]| {
+            a.iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+                .into_iter()
+        };
+
+        let poll_interval = |a: &[&str]| value_from_args(args(a), POLL_INTERVAL_FLAG);
+
+        assert_eq!(poll_interval(&["keypad-tray"]), None);
+        assert_eq!(
+            poll_interval(&["keypad-tray", "--poll-interval-ms=250"]),
+            Some("250".into())
+        );
+        assert_eq!(
+            poll_interval(&["keypad-tray", "--poll-interval-ms", "50"]),
+            Some("50".into())
+        );
+    }
+}
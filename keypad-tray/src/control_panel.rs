@@ -1,4 +1,4 @@
-use std::{cell::RefCell, cmp::min, thread};
+use std::{cell::RefCell, cmp::min, path::PathBuf, thread};
 
 use native_windows_derive as nwd;
 use native_windows_gui as nwg;
@@ -6,10 +6,15 @@ use native_windows_gui as nwg;
 use nwd::NwgUi;
 use nwg::NativeUi;
 
-use keypad::Keypad;
+use keypad::{describe_binding, ApplyMode, Board, Keypad, ProfileKeys};
 use thread::JoinHandle;
 
-use crate::{models::Profile, profile_editor::KeypadEditor};
+use crate::{
+    audit::{AuditLog, Event, SwitchReason},
+    models::{remove_profile, Profile},
+    profile_editor::KeypadEditor,
+    store::{self, ConflictResolution},
+};
 
 const UP_PNG: &[u8] = include_bytes!("../resources/up.png");
 const DOWN_PNG: &[u8] = include_bytes!("../resources/down.png");
@@ -18,11 +23,11 @@ const SUCCESS_PNG: &[u8] = include_bytes!("../resources/success.png");
 
 #[derive(Default, NwgUi)]
 pub struct ControlPanel {
-    #[nwg_control(size: (300, 500), position: (1150, 450), title: "Keypad Control Panel")]
+    #[nwg_control(size: (300, 580), position: (1150, 450), title: "Keypad Control Panel")]
     #[nwg_events( OnWindowClose: [ControlPanel::exit] )]
     window: nwg::Window,
 
-    #[nwg_layout(parent: window, min_size: [300, 500])]
+    #[nwg_layout(parent: window, min_size: [300, 580])]
     layout: nwg::GridLayout,
 
     #[nwg_control(collection: data.profile_labels(), selected_index: data.selected_index())]
@@ -86,6 +91,31 @@ pub struct ControlPanel {
     #[nwg_layout_item(layout: layout, col: 6, row: 11)]
     success_frame: nwg::ImageFrame,
 
+    #[nwg_control(text: "Import...")]
+    #[nwg_layout_item(layout: layout, col: 0, col_span: 3, row: 12)]
+    #[nwg_events(OnButtonClick: [ControlPanel::import_profiles])]
+    import_button: nwg::Button,
+
+    #[nwg_control(text: "Export...")]
+    #[nwg_layout_item(layout: layout, col: 3, col_span: 3, row: 12)]
+    #[nwg_events(OnButtonClick: [ControlPanel::export_profiles])]
+    export_button: nwg::Button,
+
+    #[nwg_control(text: "Export Cheat Sheet...")]
+    #[nwg_layout_item(layout: layout, col: 0, col_span: 6, row: 13)]
+    #[nwg_events(OnButtonClick: [ControlPanel::export_cheat_sheet])]
+    cheat_sheet_button: nwg::Button,
+
+    #[nwg_resource(title: "Import Profiles", action: nwg::FileDialogAction::Open, filters: "Keypad Profiles(*.json;*.toml)|All Files(*.*)")]
+    import_dialog: nwg::FileDialog,
+
+    #[nwg_resource(title: "Export Profiles", action: nwg::FileDialogAction::Save, filters: "Keypad Profiles(*.json;*.toml)")]
+    export_dialog: nwg::FileDialog,
+
+    // the dialog can't say which filter was picked, so each kind of export has its own
+    #[nwg_resource(title: "Export Cheat Sheet", action: nwg::FileDialogAction::Save, filters: "Key Cheat Sheet(*.svg)")]
+    cheat_sheet_dialog: nwg::FileDialog,
+
     editor_data: RefCell<Option<JoinHandle<(Option<usize>, Option<Profile>)>>>,
 
     #[nwg_control]
@@ -93,12 +123,18 @@ pub struct ControlPanel {
     editor_notice: nwg::Notice,
 
     pub profiles: RefCell<Vec<Profile>>,
+
+    audit: Option<AuditLog>,
+
+    board: Board,
 }
 
 impl ControlPanel {
-    pub fn new(profiles: Vec<Profile>) -> Self {
+    pub fn new(profiles: Vec<Profile>, audit: AuditLog, board: Board) -> Self {
         Self {
             profiles: RefCell::new(profiles),
+            audit: Some(audit),
+            board,
             ..Default::default()
         }
     }
@@ -162,7 +198,7 @@ impl ControlPanel {
                 ) {
                     return;
                 }
-                profiles.remove(idx);
+                remove_profile(&mut profiles, idx);
             }
             self.menu.set_collection(self.profile_labels());
             if self.menu.len() > 0 {
@@ -200,8 +236,8 @@ impl ControlPanel {
     fn preview_text(&self) -> String {
         match self.selected_index() {
             Some(idx) => {
-                let profile = &self.profiles.borrow()[idx];
-                get_preview_text(profile)
+                let profiles = self.profiles.borrow();
+                get_preview_text(&self.board, &profiles[idx], &profiles)
             }
             None => "Select a profile...".into(),
         }
@@ -209,7 +245,7 @@ impl ControlPanel {
 
     fn preview_profile(&self, profile: &Profile) {
         self.success_icon(false);
-        let text = get_preview_text(profile);
+        let text = get_preview_text(&self.board, profile, &self.profiles.borrow());
         self.preview.set_text(&text);
     }
 
@@ -231,9 +267,19 @@ impl ControlPanel {
         }
 
         let notice = self.editor_notice.sender();
+        let board = self.board.clone();
+        let profiles = self.profiles.borrow().clone();
 
         *handle = Some(thread::spawn(move || {
-            let editor = KeypadEditor::new(profile);
+            // the keypad is asked before the window exists so the editor never waits on it
+            let max_key = match Keypad::auto_detect().and_then(|mut k| k.get_max_key()) {
+                Ok(max_key) => max_key,
+                Err(e) => {
+                    log::info!("Not checking keys against the firmware: {}", e);
+                    None
+                }
+            };
+            let editor = KeypadEditor::new(profile, profiles, board, max_key);
             let ui = KeypadEditor::build_ui(editor).expect("Failed to build editor UI");
             nwg::dispatch_thread_events();
 
@@ -270,10 +316,27 @@ impl ControlPanel {
             return;
         }
         let idx = self.menu.selection().unwrap();
-        let profile = self.profiles.borrow()[idx].clone();
-        match Keypad::auto_detect().and_then(|mut k| k.send_combos_to_device(profile.combos)) {
-            Ok(_) => self.success_icon(true),
+        let profiles = self.profiles.borrow();
+        let profile = &profiles[idx];
+        self.audit().record(Event::Switch {
+            profile: profile.name.clone(),
+            reason: SwitchReason::Manual,
+        });
+        let result = match profile.resolve(&profiles) {
+            Ok(combos) => Keypad::auto_detect()
+                .and_then(|mut k| k.apply_profile(profile.id, combos, ApplyMode::Persistent))
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        let name = profile.name.clone();
+        drop(profiles);
+        match result {
+            Ok(_) => {
+                self.audit().record(Event::Write { profile: name });
+                self.success_icon(true);
+            }
             Err(e) => {
+                self.audit().error("write", &e);
                 nwg::simple_message("Error", &format!("Error: {}", e));
             }
         };
@@ -296,6 +359,103 @@ impl ControlPanel {
         };
     }
 
+    fn import_profiles(&self) {
+        if !self.import_dialog.run(Some(&self.window)) {
+            return;
+        }
+        let path = match self.import_dialog.get_selected_item() {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => return,
+        };
+
+        let imported = match store::import_profiles(&path) {
+            Ok(imported) => imported,
+            Err(e) => {
+                nwg::modal_error_message(self.window.handle, "Error", &format!("Error: {}", e));
+                return;
+            }
+        };
+
+        let resolution = if store::has_name_conflicts(&self.profiles.borrow(), &imported) {
+            let params = nwg::MessageParams {
+                title: "Name conflict",
+                content: "Some imported profiles have the same name as existing profiles.\r\n\r\n\
+                    Yes: overwrite the existing profiles\r\n\
+                    No: import them under a new name\r\n\
+                    Cancel: skip them",
+                buttons: nwg::MessageButtons::YesNoCancel,
+                icons: nwg::MessageIcons::Question,
+            };
+            match nwg::modal_message(self.window.handle, &params) {
+                nwg::MessageChoice::Yes => ConflictResolution::Overwrite,
+                nwg::MessageChoice::No => ConflictResolution::Rename,
+                _ => ConflictResolution::Skip,
+            }
+        } else {
+            ConflictResolution::Rename
+        };
+
+        let selection = self.menu.selection();
+        let merged = {
+            let mut profiles = self.profiles.borrow_mut();
+            store::merge_profiles(&mut profiles, imported, resolution)
+        };
+        self.menu.set_collection(self.profile_labels());
+        self.set_selection(selection.or(self.selected_index()));
+        nwg::modal_info_message(
+            self.window.handle,
+            "Import complete",
+            &format!("Imported {} profile(s).", merged),
+        );
+    }
+
+    fn export_profiles(&self) {
+        self.export(&self.export_dialog, false);
+    }
+
+    fn export_cheat_sheet(&self) {
+        self.export(&self.cheat_sheet_dialog, true);
+    }
+
+    fn export(&self, dialog: &nwg::FileDialog, cheat_sheet: bool) {
+        let profiles: Vec<Profile> = match self.menu.selection() {
+            // exported on its own, so it can't rely on its parent
+            Some(idx) => {
+                let profiles = self.profiles.borrow();
+                let profile = &profiles[idx];
+                vec![profile
+                    .flattened(&profiles)
+                    .unwrap_or_else(|_| profile.clone())]
+            }
+            None => self.profiles.borrow().clone(),
+        };
+        if profiles.is_empty() || !dialog.run(Some(&self.window)) {
+            return;
+        }
+        let mut path = match dialog.get_selected_item() {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => return,
+        };
+        if path.extension().is_none() {
+            path.set_extension(if cheat_sheet { "svg" } else { "json" });
+        }
+
+        let result = match cheat_sheet {
+            true => store::export_cheat_sheet(&path, &self.board, &profiles),
+            false => store::export_profiles(&path, &profiles),
+        };
+        match result {
+            Ok(()) => self.success_icon(true),
+            Err(e) => {
+                nwg::modal_error_message(self.window.handle, "Error", &format!("Error: {}", e));
+            }
+        }
+    }
+
+    fn audit(&self) -> &AuditLog {
+        self.audit.as_ref().expect("Audit log was not provided")
+    }
+
     fn success_icon(&self, show: bool) {
         self.success_frame.set_visible(show);
     }
@@ -305,7 +465,25 @@ impl ControlPanel {
     }
 }
 
-fn get_preview_text(profile: &Profile) -> String {
-    let combos: Vec<_> = profile.combos.iter().map(|c| format!("{}", c)).collect();
-    combos.join("\r\n")
+fn get_preview_text(board: &Board, profile: &Profile, profiles: &[Profile]) -> String {
+    let resolved = profile
+        .resolve(profiles)
+        .and_then(|combos| Ok((combos, profile.resolve_labels(profiles)?)));
+    let (combos, labels) = match resolved {
+        Ok(resolved) => resolved,
+        Err(e) => return e.to_string(),
+    };
+    let lines: Vec<_> = board
+        .keys
+        .iter()
+        .enumerate()
+        .map(|(idx, key)| {
+            let text = describe_binding(combos[idx].as_ref(), &labels[idx]);
+            match profile.inherited[idx] {
+                true => format!("{}: {} (inherited)", key.name, text),
+                false => format!("{}: {}", key.name, text),
+            }
+        })
+        .collect();
+    lines.join("\r\n")
 }
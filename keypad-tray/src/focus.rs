@@ -0,0 +1,154 @@
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FocusedWindow {
+    pub pid: u32,
+    pub title: String,
+}
+
+pub trait FocusProvider {
+    fn focused_window(&mut self) -> Option<FocusedWindow>;
+}
+
+#[cfg(windows)]
+pub fn system_focus_provider() -> Option<Box<dyn FocusProvider + Send>> {
+    Some(Box::new(windows::WindowsFocusProvider))
+}
+
+#[cfg(target_os = "linux")]
+pub fn system_focus_provider() -> Option<Box<dyn FocusProvider + Send>> {
+    match x11::X11FocusProvider::connect() {
+        Some(provider) => Some(Box::new(provider)),
+        None => {
+            log::warn!("Unable to connect to X11, focus based switching is disabled");
+            None
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn system_focus_provider() -> Option<Box<dyn FocusProvider + Send>> {
+    None
+}
+
+#[cfg(windows)]
+mod windows {
+    use winapi::um::winuser::{GetForegroundWindow, GetWindowTextW, GetWindowThreadProcessId};
+
+    use super::{FocusProvider, FocusedWindow};
+
+    pub struct WindowsFocusProvider;
+
+    impl FocusProvider for WindowsFocusProvider {
+        fn focused_window(&mut self) -> Option<FocusedWindow> {
+            unsafe {
+                let hwnd = GetForegroundWindow();
+                if hwnd.is_null() {
+                    return None;
+                }
+
+                let mut pid = 0u32;
+                GetWindowThreadProcessId(hwnd, &mut pid);
+
+                let mut buf = [0u16; 512];
+                let len = GetWindowTextW(hwnd, buf.as_mut_ptr(), buf.len() as i32);
+                let title = String::from_utf16_lossy(&buf[..len.max(0) as usize]);
+                Some(FocusedWindow { pid, title })
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use x11rb::{
+        connection::Connection,
+        protocol::xproto::{Atom, AtomEnum, ConnectionExt, Window},
+        rust_connection::RustConnection,
+    };
+
+    use super::{FocusProvider, FocusedWindow};
+
+    pub struct X11FocusProvider {
+        conn: RustConnection,
+        root: Window,
+        net_active_window: Atom,
+        net_wm_pid: Atom,
+        net_wm_name: Atom,
+        utf8_string: Atom,
+    }
+
+    impl X11FocusProvider {
+        pub fn connect() -> Option<Self> {
+            let (conn, screen) = RustConnection::connect(None).ok()?;
+            let root = conn.setup().roots.get(screen)?.root;
+            let intern = |name: &[u8]| -> Option<Atom> {
+                Some(conn.intern_atom(false, name).ok()?.reply().ok()?.atom)
+            };
+            let net_active_window = intern(b"_NET_ACTIVE_WINDOW")?;
+            let net_wm_pid = intern(b"_NET_WM_PID")?;
+            let net_wm_name = intern(b"_NET_WM_NAME")?;
+            let utf8_string = intern(b"UTF8_STRING")?;
+            Some(Self {
+                conn,
+                root,
+                net_active_window,
+                net_wm_pid,
+                net_wm_name,
+                utf8_string,
+            })
+        }
+
+        fn property32(&self, window: Window, property: Atom, type_: AtomEnum) -> Option<u32> {
+            let reply = self
+                .conn
+                .get_property(false, window, property, type_, 0, 1)
+                .ok()?
+                .reply()
+                .ok()?;
+            let mut values = reply.value32()?;
+            values.next()
+        }
+
+        fn title(&self, window: Window) -> String {
+            let reply = self
+                .conn
+                .get_property(
+                    false,
+                    window,
+                    self.net_wm_name,
+                    self.utf8_string,
+                    0,
+                    u32::MAX,
+                )
+                .ok()
+                .and_then(|cookie| cookie.reply().ok());
+            match reply {
+                Some(reply) => String::from_utf8_lossy(&reply.value).into_owned(),
+                None => String::new(),
+            }
+        }
+    }
+
+    impl FocusProvider for X11FocusProvider {
+        fn focused_window(&mut self) -> Option<FocusedWindow> {
+            let window = self.property32(self.root, self.net_active_window, AtomEnum::WINDOW)?;
+            if window == 0 {
+                return None;
+            }
+            let pid = self.property32(window, self.net_wm_pid, AtomEnum::CARDINAL)?;
+            let title = self.title(window);
+            Some(FocusedWindow { pid, title })
+        }
+    }
+}
+
+#[cfg(test)]
+pub struct FakeFocusProvider {
+    pub window: Option<FocusedWindow>,
+}
+
+#[cfg(test)]
+impl FocusProvider for FakeFocusProvider {
+    fn focused_window(&mut self) -> Option<FocusedWindow> {
+        self.window.clone()
+    }
+}
@@ -0,0 +1,92 @@
+use std::path::Path;
+
+use keypad::{binding_to_string, parse_binding, KeyCombo};
+use serde_json::{from_value, Value};
+
+use crate::store::StoreError;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ProfileFormat {
+    Json,
+    Toml,
+}
+
+impl ProfileFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => ProfileFormat::Toml,
+            _ => ProfileFormat::Json,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ProfileFormat::Json => "json",
+            ProfileFormat::Toml => "toml",
+        }
+    }
+
+    pub fn parse(self, contents: &str) -> Result<Value, StoreError> {
+        match self {
+            ProfileFormat::Json => Ok(serde_json::from_str(contents)?),
+            ProfileFormat::Toml => {
+                let mut document = toml::from_str(contents)?;
+                combos_from_strings(&mut document)?;
+                Ok(document)
+            }
+        }
+    }
+
+    pub fn write(self, document: &Value) -> Result<String, StoreError> {
+        match self {
+            ProfileFormat::Json => Ok(serde_json::to_string_pretty(document)?),
+            ProfileFormat::Toml => {
+                let mut document = document.clone();
+                combos_to_strings(&mut document)?;
+                remove_nulls(&mut document);
+                let document = toml::Value::try_from(document)?;
+                Ok(toml::to_string_pretty(&document)?)
+            }
+        }
+    }
+}
+
+fn profile_combos(document: &mut Value) -> impl Iterator<Item = &mut Value> {
+    document
+        .get_mut("profiles")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(|profile| profile.get_mut("combos").and_then(Value::as_array_mut))
+        .flatten()
+}
+
+fn combos_to_strings(document: &mut Value) -> Result<(), StoreError> {
+    for combo in profile_combos(document) {
+        let parsed: Option<KeyCombo> = from_value(combo.clone())?;
+        *combo = Value::String(binding_to_string(parsed.as_ref()));
+    }
+    Ok(())
+}
+
+fn combos_from_strings(document: &mut Value) -> Result<(), StoreError> {
+    for combo in profile_combos(document) {
+        if let Value::String(text) = combo {
+            let parsed = parse_binding(text)?;
+            *combo = serde_json::to_value(parsed)?;
+        }
+    }
+    Ok(())
+}
+
+// TOML has no null, so unset optional fields are left out entirely
+fn remove_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(remove_nulls);
+        }
+        Value::Array(items) => items.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
+}
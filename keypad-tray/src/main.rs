@@ -1,11 +1,25 @@
 #![windows_subsystem = "windows"]
 
+use std::{rc::Rc, sync::Arc};
+
 use native_windows_gui as nwg;
 use nwg::NativeUi;
 
+use audit::AuditLog;
+use keypad::Board;
+use store::{FileStore, ProfileStore};
+use watchdog::SharedSwitchState;
+
+mod audit;
+mod config;
 mod control_panel;
+mod focus;
+mod format;
 mod models;
+mod processes;
 mod profile_editor;
+mod rules;
+mod schedule;
 mod store;
 mod tray;
 mod watchdog;
@@ -13,8 +27,23 @@ mod watchdog;
 fn main() {
     nwg::init().expect("Failed to init Native Windows GUI");
     nwg::Font::set_global_family("Segoe UI").expect("Failed to set default font");
+    let file_store = FileStore::locate().expect("Failed to open profiles directory");
+    let audit = AuditLog::new(file_store.dir());
+    let board = Board::locate(file_store.dir()).unwrap_or_else(|e| {
+        log::warn!("Using the standard board layout: {}", e);
+        Board::default()
+    });
+    let store: Rc<dyn ProfileStore> = Rc::new(file_store);
+    // the tray restarts after profile edits, auto-switch pauses and pins carry over
+    let switch_state = SharedSwitchState::default();
     loop {
-        let ui = tray::KeypadTray::build_ui(Default::default()).expect("Failed to build UI");
+        let tray = tray::KeypadTray::new(
+            Rc::clone(&store),
+            audit.clone(),
+            Arc::clone(&switch_state),
+            board.clone(),
+        );
+        let ui = tray::KeypadTray::build_ui(tray).expect("Failed to build UI");
         nwg::dispatch_thread_events();
 
         if !ui.restart_tray.get() {
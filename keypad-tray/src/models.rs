@@ -1,20 +1,110 @@
 use std::fmt::Display;
 
-use keypad::{Key, KeyCombo, KeyPress};
+use keypad::{KeyCombo, KeyLabel, ProfileError, ProfileKeys};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::rules::{AutoSwitch, Trigger};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Profile {
+    // stays the same through renames and edits, the keypad stores the last applied one
+    #[serde(default = "Uuid::new_v4")]
+    pub id: Uuid,
     pub name: String,
-    pub combos: [KeyCombo; 6],
-    pub auto_launch_program: Option<String>,
+    pub parent: Option<Uuid>,
+    // keys taken from the parent, their own combos are ignored
+    #[serde(default, skip_serializing_if = "inherits_nothing")]
+    pub inherited: [bool; 6],
+    // None leaves the key unassigned
+    pub combos: [Option<KeyCombo>; 6],
+    // inherited keys take their label from the same profile as their combo
+    #[serde(default, skip_serializing_if = "unlabelled")]
+    pub labels: [KeyLabel; 6],
+    pub auto_switch: Option<AutoSwitch>,
+}
+
+impl ProfileKeys for Profile {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn parent(&self) -> Option<Uuid> {
+        self.parent
+    }
+
+    fn inherited(&self) -> &[bool; 6] {
+        &self.inherited
+    }
+
+    fn combos(&self) -> &[Option<KeyCombo>; 6] {
+        &self.combos
+    }
+
+    fn labels(&self) -> &[KeyLabel; 6] {
+        &self.labels
+    }
+}
+
+impl Profile {
+    // a copy that no longer depends on other profiles
+    pub fn flattened(&self, profiles: &[Profile]) -> Result<Profile, ProfileError> {
+        Ok(Profile {
+            combos: self.resolve(profiles)?,
+            labels: self.resolve_labels(profiles)?,
+            parent: None,
+            inherited: [false; 6],
+            ..self.clone()
+        })
+    }
+}
+
+pub fn validate_profiles(profiles: &[Profile]) -> Result<(), ProfileError> {
+    for (idx, profile) in profiles.iter().enumerate() {
+        if let Some(other) = profiles[idx + 1..].iter().find(|p| p.id == profile.id) {
+            return Err(ProfileError::DuplicateId(
+                profile.name.clone(),
+                other.name.clone(),
+            ));
+        }
+        profile.resolve(profiles)?;
+    }
+    Ok(())
+}
+
+// profiles that inherited from the removed one keep the keys they had
+pub fn remove_profile(profiles: &mut Vec<Profile>, idx: usize) -> Profile {
+    let id = profiles[idx].id;
+    for child in 0..profiles.len() {
+        if profiles[child].parent == Some(id) {
+            if let Ok(flat) = profiles[child].flattened(profiles) {
+                profiles[child] = flat;
+            }
+        }
+    }
+    profiles.remove(idx)
+}
+
+fn unlabelled(labels: &[KeyLabel; 6]) -> bool {
+    labels.iter().all(KeyLabel::is_empty)
+}
+
+fn inherits_nothing(inherited: &[bool; 6]) -> bool {
+    !inherited.contains(&true)
 }
 
 impl Display for Profile {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.name)?;
-        match self.auto_launch_program.as_ref() {
-            Some(program) => write!(f, " ({})", program)?,
+        match self.auto_switch.as_ref() {
+            Some(auto_switch) if auto_switch.trigger == Trigger::Focused => {
+                write!(f, " (focused: {})", auto_switch)?
+            }
+            Some(auto_switch) => write!(f, " ({})", auto_switch)?,
             None => {}
         };
         Ok(())
@@ -24,34 +114,103 @@ impl Display for Profile {
 impl Default for Profile {
     fn default() -> Self {
         Self {
+            id: Uuid::new_v4(),
             name: String::new(),
-            auto_launch_program: None,
-            combos: [
-                KeyCombo {
-                    one: KeyPress::key(Key::A),
-                    two: None,
-                },
-                KeyCombo {
-                    one: KeyPress::key(Key::A),
-                    two: None,
-                },
-                KeyCombo {
-                    one: KeyPress::key(Key::A),
-                    two: None,
-                },
-                KeyCombo {
-                    one: KeyPress::key(Key::A),
-                    two: None,
-                },
-                KeyCombo {
-                    one: KeyPress::key(Key::A),
-                    two: None,
-                },
-                KeyCombo {
-                    one: KeyPress::key(Key::A),
-                    two: None,
-                },
-            ],
+            parent: None,
+            inherited: [false; 6],
+            auto_switch: None,
+            combos: Default::default(),
+            labels: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str, combos: [&str; 6]) -> Profile {
+        let mut profile = Profile {
+            name: name.into(),
+            ..Default::default()
+        };
+        for (slot, combo) in combos.iter().enumerate() {
+            profile.combos[slot] = keypad::parse_binding(combo).unwrap();
         }
+        profile
+    }
+
+    fn child_of(parent: &Profile, name: &str) -> Profile {
+        let mut child = profile(name, ["None"; 6]);
+        child.parent = Some(parent.id);
+        child.inherited = [true; 6];
+        child
+    }
+
+    #[test]
+    fn inherited_keys_come_from_nearest_ancestor() {
+        let mut base = profile("Default", ["A", "B", "C", "D", "E", "F"]);
+        base.labels[2].label = Some("Select all".into());
+        let mut ide = child_of(&base, "IDE");
+        ide.combos[1] = Some("F5".parse().unwrap());
+        ide.labels[1].label = Some("Run".into());
+        ide.inherited[1] = false;
+        let mut debug = child_of(&ide, "Debug");
+        debug.combos[5] = Some("F10".parse().unwrap());
+        debug.inherited[5] = false;
+        // overridden as unassigned
+        debug.inherited[0] = false;
+        let profiles = vec![base.clone(), ide.clone(), debug.clone()];
+
+        let expected = profile("", ["None", "F5", "C", "D", "E", "F10"]).combos;
+        assert_eq!(debug.resolve(&profiles).unwrap(), expected);
+        let labels = debug.resolve_labels(&profiles).unwrap();
+        assert_eq!(labels[1].label.as_deref(), Some("Run"));
+        assert_eq!(labels[2].label.as_deref(), Some("Select all"));
+        assert!(labels[5].is_empty());
+        assert_eq!(base.resolve(&profiles).unwrap(), base.combos);
+        assert!(validate_profiles(&profiles).is_ok());
+
+        assert!(debug.inherits_from(base.id, &profiles));
+        assert!(!base.inherits_from(debug.id, &profiles));
+
+        let mut profiles = profiles;
+        remove_profile(&mut profiles, 1);
+        assert_eq!(profiles[1].parent, None);
+        assert_eq!(profiles[1].combos, expected);
+        assert!(validate_profiles(&profiles).is_ok());
+    }
+
+    #[test]
+    fn invalid_inheritance_is_rejected() {
+        let mut a = profile("A", ["A"; 6]);
+        let b = child_of(&a, "B");
+        a.parent = Some(b.id);
+        assert_eq!(
+            validate_profiles(&[a.clone(), b.clone()]),
+            Err(ProfileError::InheritanceCycle("A".into()))
+        );
+
+        let orphan = child_of(&profile("Deleted", ["A"; 6]), "Orphan");
+        assert_eq!(
+            validate_profiles(&[orphan]),
+            Err(ProfileError::MissingParent("Orphan".into()))
+        );
+
+        let mut rootless = profile("Rootless", ["A"; 6]);
+        rootless.inherited[2] = true;
+        assert_eq!(
+            validate_profiles(&[rootless]),
+            Err(ProfileError::NoParent("Rootless".into()))
+        );
+
+        let copy = Profile {
+            name: "Copy".into(),
+            ..a.clone()
+        };
+        assert_eq!(
+            validate_profiles(&[a.clone(), copy]),
+            Err(ProfileError::DuplicateId("A".into(), "Copy".into()))
+        );
     }
 }
@@ -0,0 +1,458 @@
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::{thread::sleep, time::Duration};
+
+#[cfg(not(target_os = "linux"))]
+use sysinfo::{ProcessExt, RefreshKind, System, SystemExt};
+
+use crate::{config::poll_interval_setting, rules::ProcessInfo};
+
+const DEFAULT_POLL_INTERVAL_MILLIS: u64 = 1000;
+
+pub type ProcessFilter = Box<dyn Fn(&ProcessInfo) -> bool + Send>;
+
+pub trait ProcessSource {
+    fn processes(&mut self) -> Vec<ProcessInfo>;
+
+    // only processes accepted by the filter need to wake the switcher
+    fn watch(&mut self, _filter: ProcessFilter) {}
+
+    // blocks until a watched process may have started or exited, or the timeout passes,
+    // returns false when nothing changed
+    fn wait(&mut self, timeout: Duration) -> bool {
+        sleep(timeout);
+        true
+    }
+}
+
+pub fn poll_interval() -> Duration {
+    let default = Duration::from_millis(DEFAULT_POLL_INTERVAL_MILLIS);
+    match poll_interval_setting() {
+        Ok(interval) => interval.unwrap_or(default),
+        Err(e) => {
+            log::warn!("{}, polling every {:?}", e, default);
+            default
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn system_process_source(poll_interval: Duration) -> Box<dyn ProcessSource + Send> {
+    match linux::ProcConnectorSource::connect() {
+        Ok(source) => Box::new(source),
+        Err(e) => {
+            log::warn!(
+                "Unable to listen for process events ({}), polling /proc every {:?}",
+                e,
+                poll_interval
+            );
+            Box::new(linux::ProcfsSource::new(poll_interval))
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn system_process_source(poll_interval: Duration) -> Box<dyn ProcessSource + Send> {
+    Box::new(SysinfoProcessSource::new(poll_interval))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub struct SysinfoProcessSource {
+    system: System,
+    poll_interval: Duration,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl SysinfoProcessSource {
+    pub fn new(poll_interval: Duration) -> Self {
+        Self {
+            system: System::new_with_specifics(RefreshKind::new().with_processes()),
+            poll_interval,
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl ProcessSource for SysinfoProcessSource {
+    fn processes(&mut self) -> Vec<ProcessInfo> {
+        self.system.refresh_processes();
+        self.system
+            .get_processes()
+            .iter()
+            .map(|(&pid, p)| ProcessInfo {
+                pid: pid as u32,
+                name: p.name().into(),
+                exe: p.exe().into(),
+                cmd: p.cmd().to_vec(),
+            })
+            .collect()
+    }
+
+    fn wait(&mut self, timeout: Duration) -> bool {
+        sleep(self.poll_interval.min(timeout));
+        true
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        collections::HashSet,
+        fs, io, mem,
+        os::unix::io::RawFd,
+        path::PathBuf,
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    use super::{ProcessFilter, ProcessSource};
+    use crate::rules::ProcessInfo;
+
+    const CN_IDX_PROC: u32 = 1;
+    const CN_VAL_PROC: u32 = 1;
+    const PROC_CN_MCAST_LISTEN: u32 = 1;
+    const PROC_EVENT_EXEC: u32 = 0x0000_0002;
+    const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+    // nlmsghdr (16 bytes) followed by the cn_msg header (20 bytes)
+    const EVENT_OFFSET: usize = 36;
+    // what, cpu, timestamp, then the pid and tgid of the event data
+    const EVENT_LEN: usize = 24;
+
+    pub struct ProcfsSource {
+        poll_interval: Duration,
+        filter: Option<ProcessFilter>,
+        watched: HashSet<u32>,
+    }
+
+    impl ProcfsSource {
+        pub fn new(poll_interval: Duration) -> Self {
+            Self {
+                poll_interval,
+                filter: None,
+                watched: HashSet::new(),
+            }
+        }
+    }
+
+    impl ProcessSource for ProcfsSource {
+        fn processes(&mut self) -> Vec<ProcessInfo> {
+            all_processes()
+        }
+
+        fn watch(&mut self, filter: ProcessFilter) {
+            self.watched = watched_pids(&filter);
+            self.filter = Some(filter);
+        }
+
+        fn wait(&mut self, timeout: Duration) -> bool {
+            let filter = match self.filter.as_ref() {
+                Some(filter) => filter,
+                None => {
+                    sleep(self.poll_interval.min(timeout));
+                    return true;
+                }
+            };
+            let deadline = Instant::now() + timeout;
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    return false;
+                }
+                sleep(self.poll_interval.min(deadline - now));
+                let watched = watched_pids(filter);
+                if watched != self.watched {
+                    self.watched = watched;
+                    return true;
+                }
+            }
+        }
+    }
+
+    pub struct ProcConnectorSource {
+        socket: Socket,
+        filter: Option<ProcessFilter>,
+        watched: HashSet<u32>,
+    }
+
+    impl ProcConnectorSource {
+        pub fn connect() -> io::Result<Self> {
+            let socket = Socket::connect()?;
+            Ok(Self {
+                socket,
+                filter: None,
+                watched: HashSet::new(),
+            })
+        }
+
+        // returns true when the event is for a watched process
+        fn handle_event(&mut self, what: u32, pid: u32) -> bool {
+            match what {
+                PROC_EVENT_EXEC => match read_process(pid) {
+                    Some(process) if self.filter.as_ref().map_or(true, |f| f(&process)) => {
+                        self.watched.insert(pid);
+                        true
+                    }
+                    _ => false,
+                },
+                PROC_EVENT_EXIT => self.watched.remove(&pid),
+                _ => false,
+            }
+        }
+    }
+
+    impl ProcessSource for ProcConnectorSource {
+        fn processes(&mut self) -> Vec<ProcessInfo> {
+            all_processes()
+        }
+
+        fn watch(&mut self, filter: ProcessFilter) {
+            self.watched = watched_pids(&filter);
+            self.filter = Some(filter);
+        }
+
+        fn wait(&mut self, timeout: Duration) -> bool {
+            let deadline = Instant::now() + timeout;
+            let mut buf = [0u8; 1024];
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    return false;
+                }
+                match self.socket.recv(&mut buf, deadline - now) {
+                    Ok(Some(len)) => match parse_event(&buf[..len]) {
+                        Some((what, pid)) if self.handle_event(what, pid) => return true,
+                        _ => {}
+                    },
+                    Ok(None) => return false,
+                    // the kernel dropped events, so re-evaluate everything
+                    Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => return true,
+                    Err(e) => {
+                        log::warn!("Failed to read process events: {}", e);
+                        sleep(deadline - now);
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    // returns the event type and process id, thread events are skipped
+    fn parse_event(buf: &[u8]) -> Option<(u32, u32)> {
+        let event = buf.get(EVENT_OFFSET..EVENT_OFFSET + EVENT_LEN)?;
+        let field = |offset: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&event[offset..offset + 4]);
+            u32::from_ne_bytes(bytes)
+        };
+        let (what, pid, tgid) = (field(0), field(16), field(20));
+        if pid != tgid {
+            return None;
+        }
+        Some((what, pid))
+    }
+
+    fn watched_pids(filter: &ProcessFilter) -> HashSet<u32> {
+        all_processes()
+            .into_iter()
+            .filter(|p| filter(p))
+            .map(|p| p.pid)
+            .collect()
+    }
+
+    fn all_processes() -> Vec<ProcessInfo> {
+        let entries = match fs::read_dir("/proc") {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .filter_map(read_process)
+            .collect()
+    }
+
+    fn read_process(pid: u32) -> Option<ProcessInfo> {
+        let dir = PathBuf::from(format!("/proc/{}", pid));
+        let comm = fs::read_to_string(dir.join("comm")).ok()?;
+        let exe = fs::read_link(dir.join("exe")).unwrap_or_default();
+        let cmd = fs::read(dir.join("cmdline"))
+            .unwrap_or_default()
+            .split(|&b| b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        // comm is cut off at 15 characters, so prefer the executable's name when it's readable
+        let name = match exe.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => comm.trim_end().into(),
+        };
+        Some(ProcessInfo {
+            pid,
+            name,
+            exe,
+            cmd,
+        })
+    }
+
+    struct Socket(RawFd);
+
+    impl Socket {
+        fn connect() -> io::Result<Self> {
+            let fd = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_CONNECTOR,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let socket = Socket(fd);
+
+            let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = CN_IDX_PROC;
+            let res = unsafe {
+                libc::bind(
+                    fd,
+                    &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if res < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // nlmsghdr, then a cn_msg carrying the listen op
+            let mut msg = Vec::with_capacity(EVENT_OFFSET + 4);
+            msg.extend_from_slice(&(EVENT_OFFSET as u32 + 4).to_ne_bytes());
+            msg.extend_from_slice(&(libc::NLMSG_DONE as u16).to_ne_bytes());
+            msg.extend_from_slice(&0u16.to_ne_bytes());
+            msg.extend_from_slice(&0u32.to_ne_bytes());
+            msg.extend_from_slice(&std::process::id().to_ne_bytes());
+            msg.extend_from_slice(&CN_IDX_PROC.to_ne_bytes());
+            msg.extend_from_slice(&CN_VAL_PROC.to_ne_bytes());
+            msg.extend_from_slice(&0u32.to_ne_bytes());
+            msg.extend_from_slice(&0u32.to_ne_bytes());
+            msg.extend_from_slice(&4u16.to_ne_bytes());
+            msg.extend_from_slice(&0u16.to_ne_bytes());
+            msg.extend_from_slice(&PROC_CN_MCAST_LISTEN.to_ne_bytes());
+            let sent = unsafe { libc::send(fd, msg.as_ptr() as *const libc::c_void, msg.len(), 0) };
+            if sent < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(socket)
+        }
+
+        // returns None when nothing arrived before the timeout
+        fn recv(&self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+            let mut fds = libc::pollfd {
+                fd: self.0,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+            let ready = unsafe { libc::poll(&mut fds, 1, millis) };
+            if ready < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if ready == 0 {
+                return Ok(None);
+            }
+            let len =
+                unsafe { libc::recv(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Some(len as usize))
+        }
+    }
+
+    impl Drop for Socket {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.0);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn event(what: u32, pid: u32, tgid: u32) -> Vec<u8> {
+            let mut buf = vec![0u8; EVENT_OFFSET];
+            buf.extend_from_slice(&what.to_ne_bytes());
+            buf.extend_from_slice(&[0u8; 12]);
+            buf.extend_from_slice(&pid.to_ne_bytes());
+            buf.extend_from_slice(&tgid.to_ne_bytes());
+            buf
+        }
+
+        #[test]
+        fn events_are_parsed() {
+            assert_eq!(
+                parse_event(&event(PROC_EVENT_EXEC, 42, 42)),
+                Some((PROC_EVENT_EXEC, 42))
+            );
+            assert_eq!(
+                parse_event(&event(PROC_EVENT_EXIT, 42, 42)),
+                Some((PROC_EVENT_EXIT, 42))
+            );
+            // thread of process 42
+            assert_eq!(parse_event(&event(PROC_EVENT_EXIT, 43, 42)), None);
+            assert_eq!(parse_event(&event(PROC_EVENT_EXEC, 42, 42)[..40]), None);
+        }
+
+        #[test]
+        fn current_process_is_read_from_proc() {
+            let process = read_process(std::process::id()).unwrap();
+            assert_eq!(
+                process.exe.file_name(),
+                std::env::current_exe().unwrap().file_name()
+            );
+            assert!(!process.cmd.is_empty());
+        }
+    }
+}
+
+// Returns one snapshot per call, repeating the last one once the script runs out
+#[cfg(test)]
+pub struct ScriptedProcessSource {
+    script: VecDeque<Vec<ProcessInfo>>,
+    last: Vec<ProcessInfo>,
+}
+
+#[cfg(test)]
+impl ScriptedProcessSource {
+    pub fn new(script: Vec<Vec<&str>>) -> Self {
+        let script = script
+            .into_iter()
+            .map(|names| {
+                names
+                    .into_iter()
+                    .enumerate()
+                    .map(|(pid, name)| ProcessInfo {
+                        pid: pid as u32 + 1,
+                        name: name.into(),
+                        ..Default::default()
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            script,
+            last: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ProcessSource for ScriptedProcessSource {
+    fn processes(&mut self) -> Vec<ProcessInfo> {
+        if let Some(next) = self.script.pop_front() {
+            self.last = next;
+        }
+        self.last.clone()
+    }
+}
@@ -6,17 +6,23 @@ use native_windows_gui as nwg;
 use nwd::{NwgPartial, NwgUi};
 use nwg::{CheckBoxState, GridLayoutItem};
 
-use keypad::{Key, KeyCombo, KeyPress};
+use keypad::{
+    describe_binding, lint_combos, Board, BoardKey, Key, KeyCombo, KeyLabel, KeyPress, ProfileKeys,
+};
+use uuid::Uuid;
 
-use crate::models::Profile;
+use crate::{
+    models::Profile,
+    rules::{AutoSwitch, Trigger},
+};
 
 #[derive(Default, NwgUi)]
 pub struct KeypadEditor {
-    #[nwg_control(size: (850, 300), title: "Keypad Profile Editor")]
-    #[nwg_events( OnWindowClose: [KeypadEditor::exit] )]
+    #[nwg_control(size: (850, 330), title: "Keypad Profile Editor")]
+    #[nwg_events( OnWindowClose: [KeypadEditor::exit], OnInit: [KeypadEditor::show_keys] )]
     window: nwg::Window,
 
-    #[nwg_layout(parent: window, min_size: [300, 300])]
+    #[nwg_layout(parent: window, min_size: [300, 330])]
     layout: nwg::GridLayout,
 
     #[nwg_control(text: &data.name())]
@@ -42,8 +48,33 @@ pub struct KeypadEditor {
     #[nwg_events(OnButtonClick: [KeypadEditor::save_clicked])]
     save_button: nwg::Button,
 
+    #[nwg_control(text: "Focused", check_state: data.focused_checkstate())]
+    #[nwg_layout_item(layout: layout, col: 2, row: 5)]
+    focused_only: nwg::CheckBox,
+
+    #[nwg_control(text: "Sticky", check_state: data.sticky_checkstate())]
+    #[nwg_layout_item(layout: layout, col: 0, row: 6)]
+    sticky: nwg::CheckBox,
+
+    #[nwg_control(text: "Priority")]
+    #[nwg_layout_item(layout: layout, col: 1, row: 6)]
+    priority_label: nwg::Label,
+
+    #[nwg_control(text: &data.priority())]
+    #[nwg_layout_item(layout: layout, col: 2, row: 6)]
+    priority: nwg::TextInput,
+
+    #[nwg_control(text: "Inherits from")]
+    #[nwg_layout_item(layout: layout, col: 0, row: 7)]
+    parent_label: nwg::Label,
+
+    #[nwg_control(collection: data.parent_names(), selected_index: data.parent_idx())]
+    #[nwg_layout_item(layout: layout, col: 1, col_span: 2, row: 7)]
+    #[nwg_events( OnComboxBoxSelection: [KeypadEditor::parent_selected] )]
+    parent: nwg::ComboBox<String>,
+
     #[nwg_control]
-    #[nwg_layout_item(layout: layout, col: 3, col_span: 4, row: 0, row_span: 6)]
+    #[nwg_layout_item(layout: layout, col: 3, col_span: 4, row: 0, row_span: 8)]
     frame1: nwg::Frame,
 
     #[nwg_control(flags: "BORDER")]
@@ -62,31 +93,60 @@ pub struct KeypadEditor {
     frame6: nwg::Frame,
 
     #[nwg_partial(parent: frame1)]
-    #[nwg_events( (apply_btn, OnButtonClick): [KeypadEditor::apply_combo] )]
+    #[nwg_events(
+        (apply_btn, OnButtonClick): [KeypadEditor::apply_combo],
+        (inherit, OnButtonClick): [KeypadEditor::inherit_toggled]
+    )]
     key1: KeyComboPartial,
 
     #[nwg_partial(parent: frame2)]
-    #[nwg_events( (apply_btn, OnButtonClick): [KeypadEditor::apply_combo] )]
+    #[nwg_events(
+        (apply_btn, OnButtonClick): [KeypadEditor::apply_combo],
+        (inherit, OnButtonClick): [KeypadEditor::inherit_toggled]
+    )]
     key2: KeyComboPartial,
 
     #[nwg_partial(parent: frame3)]
-    #[nwg_events( (apply_btn, OnButtonClick): [KeypadEditor::apply_combo] )]
+    #[nwg_events(
+        (apply_btn, OnButtonClick): [KeypadEditor::apply_combo],
+        (inherit, OnButtonClick): [KeypadEditor::inherit_toggled]
+    )]
     key3: KeyComboPartial,
 
     #[nwg_partial(parent: frame4)]
-    #[nwg_events( (apply_btn, OnButtonClick): [KeypadEditor::apply_combo] )]
+    #[nwg_events(
+        (apply_btn, OnButtonClick): [KeypadEditor::apply_combo],
+        (inherit, OnButtonClick): [KeypadEditor::inherit_toggled]
+    )]
     key4: KeyComboPartial,
 
     #[nwg_partial(parent: frame5)]
-    #[nwg_events( (apply_btn, OnButtonClick): [KeypadEditor::apply_combo] )]
+    #[nwg_events(
+        (apply_btn, OnButtonClick): [KeypadEditor::apply_combo],
+        (inherit, OnButtonClick): [KeypadEditor::inherit_toggled]
+    )]
     key5: KeyComboPartial,
 
     #[nwg_partial(parent: frame6)]
-    #[nwg_events( (apply_btn, OnButtonClick): [KeypadEditor::apply_combo] )]
+    #[nwg_events(
+        (apply_btn, OnButtonClick): [KeypadEditor::apply_combo],
+        (inherit, OnButtonClick): [KeypadEditor::inherit_toggled]
+    )]
     key6: KeyComboPartial,
 
     profile: RefCell<Profile>,
 
+    // every profile as it was when the editor opened, inherited keys come from these
+    profiles: Vec<Profile>,
+
+    // the choices in the parent list, starting with no parent
+    parents: Vec<Option<Uuid>>,
+
+    board: Board,
+
+    // read from the keypad before the editor opens, None skips the firmware check
+    max_key: Option<Key>,
+
     pub profile_new: RefCell<Option<Profile>>,
 }
 
@@ -100,7 +160,32 @@ impl KeypadEditor {
     }
 
     fn enable_auto(&self) -> bool {
-        self.profile.borrow().auto_launch_program.is_some()
+        self.profile.borrow().auto_switch.is_some()
+    }
+
+    fn focused_checkstate(&self) -> CheckBoxState {
+        let trigger = self
+            .profile
+            .borrow()
+            .auto_switch
+            .as_ref()
+            .map(|a| a.trigger);
+        bool_to_checkbox(trigger == Some(Trigger::Focused))
+    }
+
+    fn sticky_checkstate(&self) -> CheckBoxState {
+        let sticky = self.profile.borrow().auto_switch.as_ref().map(|a| a.sticky);
+        option_to_checkbox(sticky)
+    }
+
+    fn priority(&self) -> String {
+        let priority = self
+            .profile
+            .borrow()
+            .auto_switch
+            .as_ref()
+            .map(|a| a.priority);
+        priority.unwrap_or(0).to_string()
     }
 
     fn auto_program_toggled(&self) {
@@ -114,20 +199,110 @@ impl KeypadEditor {
     fn program(&self) -> String {
         self.profile
             .borrow()
-            .auto_launch_program
-            .clone()
+            .auto_switch
+            .as_ref()
+            .map(|a| a.to_string())
             .unwrap_or_else(|| String::new())
     }
 
     fn labels(&self) -> Vec<String> {
-        self.profile
-            .borrow()
-            .combos
+        let (combos, labels) = self.resolved();
+        let inherited = self.profile.borrow().inherited;
+        self.board
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(idx, key)| key_label(key, combos[idx].as_ref(), &labels[idx], inherited[idx]))
+            .collect()
+    }
+
+    // what each key will do, a broken inheritance chain shows the profile's own keys
+    fn resolved(&self) -> ([Option<KeyCombo>; 6], [KeyLabel; 6]) {
+        let profile = self.profile.borrow();
+        let combos = profile
+            .resolve(&self.profiles)
+            .unwrap_or_else(|_| profile.combos.clone());
+        let labels = profile
+            .resolve_labels(&self.profiles)
+            .unwrap_or_else(|_| profile.labels.clone());
+        (combos, labels)
+    }
+
+    fn parent_names(&self) -> Vec<String> {
+        self.parents
             .iter()
-            .map(|combo| format!("{}", combo))
+            .map(|parent| match parent {
+                Some(id) => self
+                    .profiles
+                    .iter()
+                    .find(|p| p.id == *id)
+                    .map(|p| p.name.clone())
+                    .unwrap_or_default(),
+                None => "None".into(),
+            })
             .collect()
     }
 
+    fn parent_idx(&self) -> Option<usize> {
+        let parent = self.profile.borrow().parent;
+        self.parents.iter().position(|&id| id == parent)
+    }
+
+    fn parent_selected(&self) {
+        let parent = self.parent.selection().and_then(|idx| self.parents[idx]);
+        {
+            let mut profile = self.profile.borrow_mut();
+            // without a parent, inherited keys keep what they had
+            if parent.is_none() {
+                let flat = profile.flattened(&self.profiles);
+                if let Ok(flat) = flat {
+                    *profile = flat;
+                }
+                profile.inherited = [false; 6];
+            }
+            profile.parent = parent;
+        }
+        self.show_keys();
+    }
+
+    fn inherit_toggled(&self) {
+        let (combos, labels) = self.resolved();
+        {
+            let mut profile = self.profile.borrow_mut();
+            for (idx, partial) in self.key_partials().iter().enumerate() {
+                let inherit = checkbox_to_bool(partial.inherit.check_state());
+                // a key that stops inheriting starts out with what it inherited
+                if profile.inherited[idx] && !inherit {
+                    profile.combos[idx] = combos[idx].clone();
+                    profile.labels[idx] = labels[idx].clone();
+                }
+                profile.inherited[idx] = inherit;
+            }
+        }
+        self.show_keys();
+    }
+
+    fn show_keys(&self) {
+        let (combos, _) = self.resolved();
+        let (inherited, has_parent) = {
+            let profile = self.profile.borrow();
+            (profile.inherited, profile.parent.is_some())
+        };
+        for (idx, partial) in self.key_partials().iter().enumerate() {
+            partial.show(combos[idx].clone(), inherited[idx], has_parent);
+        }
+
+        let selection = self.menu.selection();
+        self.menu.set_collection(self.labels());
+        self.menu.set_selection(selection);
+    }
+
+    fn key_partials(&self) -> [&KeyComboPartial; 6] {
+        [
+            &self.key1, &self.key2, &self.key3, &self.key4, &self.key5, &self.key6,
+        ]
+    }
+
     fn select_key(&self) {
         self.frame1.set_visible(false);
         self.frame2.set_visible(false);
@@ -158,32 +333,32 @@ impl KeypadEditor {
 
         match self.menu.selection() {
             None | Some(0) => {
-                let child = GridLayoutItem::new(&self.frame1, 3, 0, 4, 6);
+                let child = GridLayoutItem::new(&self.frame1, 3, 0, 4, 8);
                 layout.add_child_item(child);
                 self.frame1.set_visible(true);
             }
             Some(1) => {
-                let child = GridLayoutItem::new(&self.frame2, 3, 0, 4, 6);
+                let child = GridLayoutItem::new(&self.frame2, 3, 0, 4, 8);
                 layout.add_child_item(child);
                 self.frame2.set_visible(true);
             }
             Some(2) => {
-                let child = GridLayoutItem::new(&self.frame3, 3, 0, 4, 6);
+                let child = GridLayoutItem::new(&self.frame3, 3, 0, 4, 8);
                 layout.add_child_item(child);
                 self.frame3.set_visible(true);
             }
             Some(3) => {
-                let child = GridLayoutItem::new(&self.frame4, 3, 0, 4, 6);
+                let child = GridLayoutItem::new(&self.frame4, 3, 0, 4, 8);
                 layout.add_child_item(child);
                 self.frame4.set_visible(true);
             }
             Some(4) => {
-                let child = GridLayoutItem::new(&self.frame5, 3, 0, 4, 6);
+                let child = GridLayoutItem::new(&self.frame5, 3, 0, 4, 8);
                 layout.add_child_item(child);
                 self.frame5.set_visible(true);
             }
             Some(5) => {
-                let child = GridLayoutItem::new(&self.frame6, 3, 0, 4, 6);
+                let child = GridLayoutItem::new(&self.frame6, 3, 0, 4, 8);
                 layout.add_child_item(child);
                 self.frame6.set_visible(true);
             }
@@ -192,27 +367,16 @@ impl KeypadEditor {
     }
 
     fn apply_combo(&self) {
-        self.update_combo_and_label(&self.key1, 0);
-        self.update_combo_and_label(&self.key2, 1);
-        self.update_combo_and_label(&self.key3, 2);
-        self.update_combo_and_label(&self.key4, 3);
-        self.update_combo_and_label(&self.key5, 4);
-        self.update_combo_and_label(&self.key6, 5);
-    }
-
-    fn update_combo_and_label(&self, key_partial: &KeyComboPartial, idx: usize) {
-        let combo = key_partial.combo.borrow();
-        let new_label = format!("{}", combo);
-        {
-            let mut labels = self.menu.collection_mut();
-            labels[idx] = new_label;
-        }
-        self.menu.sync();
-
         {
             let mut profile = self.profile.borrow_mut();
-            profile.combos[idx] = combo.clone();
+            for (idx, partial) in self.key_partials().iter().enumerate() {
+                // inherited keys can't be edited until they stop inheriting
+                if !profile.inherited[idx] {
+                    profile.combos[idx] = partial.combo.borrow().clone();
+                }
+            }
         }
+        self.show_keys();
     }
 
     fn save_clicked(&self) {
@@ -221,24 +385,90 @@ impl KeypadEditor {
             return;
         }
 
+        let priority = match self.priority.text().trim().parse::<i32>() {
+            Ok(priority) => priority,
+            Err(_) => {
+                nwg::error_message("Invalid priority", "Priority must be a whole number.");
+                return;
+            }
+        };
+
+        let rules = self.auto_program.text();
+        let use_auto = checkbox_to_bool(self.use_auto.check_state());
+        let auto_switch = if use_auto && rules.trim().len() > 0 {
+            match rules.parse::<AutoSwitch>() {
+                Ok(auto_switch) => Some(AutoSwitch {
+                    trigger: match checkbox_to_bool(self.focused_only.check_state()) {
+                        true => Trigger::Focused,
+                        false => Trigger::Running,
+                    },
+                    sticky: checkbox_to_bool(self.sticky.check_state()),
+                    priority,
+                    ..auto_switch
+                }),
+                Err(e) => {
+                    nwg::error_message("Invalid auto-switch rules", &format!("{}", e));
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+
+        if !self.combos_look_right() {
+            return;
+        }
+
         {
             let mut profile = self.profile.borrow_mut();
             profile.name = self.name.text();
-            profile.auto_launch_program = match checkbox_to_bool(self.use_auto.check_state()) {
-                true => Some(self.auto_program.text()),
-                false => None,
-            }
+            profile.auto_switch = auto_switch;
         }
         let mut new = self.profile_new.borrow_mut();
         *new = Some(self.profile.borrow().clone());
         nwg::stop_thread_dispatch();
     }
 
+    fn combos_look_right(&self) -> bool {
+        let (combos, _) = self.resolved();
+        let warnings = lint_combos(&combos, &self.board, self.max_key);
+        if warnings.is_empty() {
+            return true;
+        }
+
+        let warnings: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+        let params = nwg::MessageParams {
+            title: "Check key combos",
+            content: &format!("{}\r\n\r\nSave anyway?", warnings.join("\r\n")),
+            buttons: nwg::MessageButtons::YesNo,
+            icons: nwg::MessageIcons::Warning,
+        };
+        match nwg::modal_message(self.window.handle, &params) {
+            nwg::MessageChoice::Yes => true,
+            _ => false,
+        }
+    }
+
     fn exit(&self) {
         nwg::stop_thread_dispatch();
     }
 
-    pub fn new(profile: Profile) -> Self {
+    pub fn new(
+        profile: Profile,
+        profiles: Vec<Profile>,
+        board: Board,
+        max_key: Option<Key>,
+    ) -> Self {
+        // a profile can't inherit from itself or from anything that inherits from it
+        let parents = Some(None)
+            .into_iter()
+            .chain(
+                profiles
+                    .iter()
+                    .filter(|p| !p.inherits_from(profile.id, &profiles))
+                    .map(|p| Some(p.id)),
+            )
+            .collect();
         Self {
             key1: KeyComboPartial::new(profile.combos[0].clone()),
             key2: KeyComboPartial::new(profile.combos[1].clone()),
@@ -247,6 +477,10 @@ impl KeypadEditor {
             key5: KeyComboPartial::new(profile.combos[4].clone()),
             key6: KeyComboPartial::new(profile.combos[5].clone()),
             profile: RefCell::new(profile),
+            profiles,
+            parents,
+            board,
+            max_key,
             ..Default::default()
         }
     }
@@ -257,36 +491,44 @@ pub struct KeyComboPartial {
     #[nwg_layout(min_size: [100, 120])]
     layout: nwg::GridLayout,
 
-    #[nwg_control(text: "Ctrl", check_state: data.ctrl1())]
+    // inherited keys show the parent's combo and can't be changed here
+    #[nwg_control(text: "Inherit")]
+    #[nwg_layout_item(layout: layout, col: 0, row: 0, row_span: 2)]
+    inherit: nwg::CheckBox,
+
+    #[nwg_control(text: "Ctrl", check_state: data.ctrl1(), enabled: data.has_key1())]
     #[nwg_layout_item(layout: layout, col: 0, row: 2, row_span: 2)]
     ctrl1: nwg::CheckBox,
 
-    #[nwg_control(text: "Alt", check_state: data.alt1())]
+    #[nwg_control(text: "Alt", check_state: data.alt1(), enabled: data.has_key1())]
     #[nwg_layout_item(layout: layout, col: 0, row: 4, row_span: 2)]
     alt1: nwg::CheckBox,
 
-    #[nwg_control(text: "Shift", check_state: data.shift1())]
+    #[nwg_control(text: "Shift", check_state: data.shift1(), enabled: data.has_key1())]
     #[nwg_layout_item(layout: layout, col: 0, row: 6, row_span: 2)]
     shift1: nwg::CheckBox,
 
-    #[nwg_control(text: "Windows", check_state: data.windows1())]
+    #[nwg_control(text: "Windows", check_state: data.windows1(), enabled: data.has_key1())]
     #[nwg_layout_item(layout: layout, col: 0, row: 8, row_span: 2)]
     windows1: nwg::CheckBox,
 
-    #[nwg_control(text: "Press 1")]
+    // unchecked leaves the key unassigned
+    #[nwg_control(text: "Press 1", check_state: data.key1_checkbox())]
     #[nwg_layout_item(layout: layout, col: 1, row: 0, row_span: 1)]
-    label1: nwg::Label,
+    #[nwg_events(OnButtonClick: [KeyComboPartial::update_enabled])]
+    enable_key1: nwg::CheckBox,
 
     #[nwg_control(
         collection: Key::ALL.iter().map(|k| format!("{}", k)).collect(),
-        selected_index: data.key1_idx()
+        selected_index: data.key1_idx(),
+        enabled: data.has_key1(),
     )]
     #[nwg_layout_item(layout: layout, col: 1, row: 1, row_span: 19)]
     key1: nwg::ListBox<String>,
 
-    #[nwg_control(text: "Press 2", check_state: data.key2_checkbox())]
+    #[nwg_control(text: "Press 2", check_state: data.key2_checkbox(), enabled: data.has_key1())]
     #[nwg_layout_item(layout: layout, col: 2, row: 0, row_span: 2)]
-    #[nwg_events(OnButtonClick: [KeyComboPartial::key2_checkbox_clicked])]
+    #[nwg_events(OnButtonClick: [KeyComboPartial::update_enabled])]
     enable_key2: nwg::CheckBox,
 
     #[nwg_control(text: "Ctrl", check_state: data.ctrl2(), enabled: data.has_key2())]
@@ -318,92 +560,131 @@ pub struct KeyComboPartial {
     #[nwg_events(OnButtonClick: [KeyComboPartial::save_clicked])]
     apply_btn: nwg::Button,
 
-    combo: RefCell<KeyCombo>,
+    combo: RefCell<Option<KeyCombo>>,
 }
 
 impl KeyComboPartial {
-    pub fn new(combo: KeyCombo) -> Self {
+    pub fn new(combo: Option<KeyCombo>) -> Self {
         Self {
             combo: RefCell::new(combo),
             ..Default::default()
         }
     }
 
+    fn one(&self) -> Option<KeyPress> {
+        self.combo.borrow().as_ref().map(|c| c.one.clone())
+    }
+
+    fn two(&self) -> Option<KeyPress> {
+        self.combo.borrow().as_ref().and_then(|c| c.two.clone())
+    }
+
+    // keys that aren't inherited keep any changes that haven't been applied yet
+    pub fn show(&self, combo: Option<KeyCombo>, inherited: bool, can_inherit: bool) {
+        self.inherit.set_check_state(bool_to_checkbox(inherited));
+        self.inherit.set_enabled(can_inherit);
+        if inherited {
+            self.combo.replace(combo);
+            self.enable_key1.set_check_state(self.key1_checkbox());
+            self.ctrl1.set_check_state(self.ctrl1());
+            self.alt1.set_check_state(self.alt1());
+            self.shift1.set_check_state(self.shift1());
+            self.windows1.set_check_state(self.windows1());
+            self.key1.set_selection(self.key1_idx());
+            self.enable_key2.set_check_state(self.key2_checkbox());
+            self.ctrl2.set_check_state(self.ctrl2());
+            self.alt2.set_check_state(self.alt2());
+            self.shift2.set_check_state(self.shift2());
+            self.windows2.set_check_state(self.windows2());
+            self.key2.set_selection(self.key2_idx());
+        }
+        self.update_enabled();
+    }
+
+    fn key1_checkbox(&self) -> CheckBoxState {
+        bool_to_checkbox(self.has_key1())
+    }
+
+    fn has_key1(&self) -> bool {
+        self.combo.borrow().is_some()
+    }
+
     fn ctrl1(&self) -> CheckBoxState {
-        bool_to_checkbox(self.combo.borrow().one.ctrl)
+        option_to_checkbox(self.one().map(|p| p.ctrl))
     }
 
     fn alt1(&self) -> CheckBoxState {
-        bool_to_checkbox(self.combo.borrow().one.alt)
+        option_to_checkbox(self.one().map(|p| p.alt))
     }
 
     fn shift1(&self) -> CheckBoxState {
-        bool_to_checkbox(self.combo.borrow().one.shift)
+        option_to_checkbox(self.one().map(|p| p.shift))
     }
 
     fn windows1(&self) -> CheckBoxState {
-        bool_to_checkbox(self.combo.borrow().one.windows)
+        option_to_checkbox(self.one().map(|p| p.windows))
     }
 
     fn key1_idx(&self) -> Option<usize> {
-        Key::ALL
-            .iter()
-            .enumerate()
-            .find(|(_, &k)| k == self.combo.borrow().one.key)
-            .map(|(i, _)| i)
+        self.one().and_then(|press| key_index(press.key))
     }
 
     fn key2_checkbox(&self) -> CheckBoxState {
-        match self.combo.borrow().two {
-            Some(_) => CheckBoxState::Checked,
-            None => CheckBoxState::Unchecked,
-        }
+        bool_to_checkbox(self.has_key2())
     }
 
-    fn key2_checkbox_clicked(&self) {
-        let enable = match self.enable_key2.check_state() {
-            CheckBoxState::Checked => true,
-            _ => false,
-        };
-        self.ctrl2.set_enabled(enable);
-        self.alt2.set_enabled(enable);
-        self.shift2.set_enabled(enable);
-        self.windows2.set_enabled(enable);
-        self.key2.set_enabled(enable);
+    // the second press only applies when the first one is assigned
+    fn update_enabled(&self) {
+        let editable = !checkbox_to_bool(self.inherit.check_state());
+        self.enable_key1.set_enabled(editable);
+        self.apply_btn.set_enabled(editable);
+
+        let enable1 = editable && checkbox_to_bool(self.enable_key1.check_state());
+        self.ctrl1.set_enabled(enable1);
+        self.alt1.set_enabled(enable1);
+        self.shift1.set_enabled(enable1);
+        self.windows1.set_enabled(enable1);
+        self.key1.set_enabled(enable1);
+        self.enable_key2.set_enabled(enable1);
+
+        let enable2 = enable1 && checkbox_to_bool(self.enable_key2.check_state());
+        self.ctrl2.set_enabled(enable2);
+        self.alt2.set_enabled(enable2);
+        self.shift2.set_enabled(enable2);
+        self.windows2.set_enabled(enable2);
+        self.key2.set_enabled(enable2);
     }
 
     fn has_key2(&self) -> bool {
-        self.combo.borrow().two.is_some()
+        self.two().is_some()
     }
 
     fn ctrl2(&self) -> CheckBoxState {
-        option_to_checkbox(self.combo.borrow().two.as_ref().map(|p| p.ctrl))
+        option_to_checkbox(self.two().map(|p| p.ctrl))
     }
 
     fn alt2(&self) -> CheckBoxState {
-        option_to_checkbox(self.combo.borrow().two.as_ref().map(|p| p.alt))
+        option_to_checkbox(self.two().map(|p| p.alt))
     }
 
     fn shift2(&self) -> CheckBoxState {
-        option_to_checkbox(self.combo.borrow().two.as_ref().map(|p| p.shift))
+        option_to_checkbox(self.two().map(|p| p.shift))
     }
 
     fn windows2(&self) -> CheckBoxState {
-        option_to_checkbox(self.combo.borrow().two.as_ref().map(|p| p.windows))
+        option_to_checkbox(self.two().map(|p| p.windows))
     }
 
     fn key2_idx(&self) -> Option<usize> {
-        match self.combo.borrow().two.as_ref() {
-            None => None,
-            Some(press) => Key::ALL
-                .iter()
-                .enumerate()
-                .find(|(_, &k)| k == press.key)
-                .map(|(i, _)| i),
-        }
+        self.two().and_then(|press| key_index(press.key))
     }
 
     fn save_clicked(&self) {
+        if !checkbox_to_bool(self.enable_key1.check_state()) {
+            self.combo.replace(None);
+            return;
+        }
+
         let key1 = match self.key1.selection() {
             Some(idx) => index_to_key(idx),
             None => {
@@ -438,7 +719,7 @@ impl KeyComboPartial {
             None
         };
 
-        self.combo.replace(KeyCombo { one, two });
+        self.combo.replace(Some(KeyCombo { one, two }));
     }
 }
 
@@ -463,3 +744,20 @@ fn option_to_checkbox(val: Option<bool>) -> CheckBoxState {
 fn index_to_key(idx: usize) -> Key {
     Key::ALL[idx].clone()
 }
+
+fn key_index(key: Key) -> Option<usize> {
+    Key::ALL.iter().position(|&k| k == key)
+}
+
+fn key_label(
+    key: &BoardKey,
+    combo: Option<&KeyCombo>,
+    label: &KeyLabel,
+    inherited: bool,
+) -> String {
+    let label = describe_binding(combo, label);
+    match inherited {
+        true => format!("{}: {} (inherited)", key.name, label),
+        false => format!("{}: {}", key.name, label),
+    }
+}
@@ -0,0 +1,475 @@
+use std::{
+    fmt::{Display, Formatter},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use chrono::NaiveDateTime;
+use glob::MatchOptions;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{focus::FocusedWindow, schedule::Schedule};
+
+// between two rules, ' | ' means any of them and ' & ' means all of them
+const SEPARATORS: [(&str, RuleMode); 2] = [(" | ", RuleMode::Any), (" & ", RuleMode::All)];
+const TARGETS: [&str; 5] = ["name", "path", "args", "title", "time"];
+
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Error, Debug)]
+pub enum RuleError {
+    #[error("Rule is empty")]
+    Empty,
+    #[error("Rules can't mix ' | ' and ' & '")]
+    MixedModes,
+    #[error("Invalid glob pattern: {0}")]
+    InvalidGlob(#[from] glob::PatternError),
+    #[error("Invalid regex: {0}")]
+    InvalidRegex(#[from] regex::Error),
+    #[error("Invalid schedule: {0}")]
+    InvalidSchedule(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub name: String,
+    pub exe: PathBuf,
+    pub cmd: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleMode {
+    Any,
+    All,
+}
+
+impl Default for RuleMode {
+    fn default() -> Self {
+        RuleMode::Any
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    Running,
+    Focused,
+}
+
+impl Default for Trigger {
+    fn default() -> Self {
+        Trigger::Running
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+    Exact(String),
+    Glob(String),
+    Regex(String),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    ProcessName(Pattern),
+    ExecutablePath(Pattern),
+    CommandLine(Pattern),
+    WindowTitle(Pattern),
+    Schedule(Schedule),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct AutoSwitch {
+    #[serde(default)]
+    pub trigger: Trigger,
+    #[serde(default)]
+    pub mode: RuleMode,
+    pub rules: Vec<Rule>,
+    // higher priority profiles win when several match
+    #[serde(default)]
+    pub priority: i32,
+    // once active, keep the profile until its rules stop matching
+    #[serde(default)]
+    pub sticky: bool,
+}
+
+impl AutoSwitch {
+    pub fn process(name: &str) -> Self {
+        Self {
+            trigger: Trigger::Running,
+            mode: RuleMode::Any,
+            rules: vec![Rule::ProcessName(Pattern::Exact(name.into()))],
+            priority: 0,
+            sticky: false,
+        }
+    }
+
+    pub fn compile(&self) -> Result<CompiledAutoSwitch, RuleError> {
+        let mut rules = Vec::new();
+        let mut schedules = Vec::new();
+        for rule in self.rules.iter() {
+            let (target, pattern) = match rule {
+                Rule::ProcessName(p) => (Target::ProcessName, p),
+                Rule::ExecutablePath(p) => (Target::ExecutablePath, p),
+                Rule::CommandLine(p) => (Target::CommandLine, p),
+                Rule::WindowTitle(p) => (Target::WindowTitle, p),
+                Rule::Schedule(schedule) => {
+                    schedules.push(schedule.clone());
+                    continue;
+                }
+            };
+            rules.push((target, pattern.compile()?));
+        }
+        Ok(CompiledAutoSwitch {
+            trigger: self.trigger,
+            mode: self.mode,
+            rules,
+            schedules,
+            priority: self.priority,
+            sticky: self.sticky,
+        })
+    }
+}
+
+impl Pattern {
+    fn compile(&self) -> Result<Matcher, RuleError> {
+        let matcher = match self {
+            Pattern::Exact(text) => Matcher::Exact(text.clone()),
+            Pattern::Glob(text) => Matcher::Glob(glob::Pattern::new(text)?),
+            Pattern::Regex(text) => {
+                Matcher::Regex(RegexBuilder::new(text).case_insensitive(true).build()?)
+            }
+        };
+        Ok(matcher)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    ProcessName,
+    ExecutablePath,
+    CommandLine,
+    WindowTitle,
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Exact(String),
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn is_match(&self, text: &str) -> bool {
+        match self {
+            Matcher::Exact(expected) => expected.eq_ignore_ascii_case(text),
+            Matcher::Glob(pattern) => pattern.matches_with(text, GLOB_OPTIONS),
+            Matcher::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledAutoSwitch {
+    trigger: Trigger,
+    mode: RuleMode,
+    rules: Vec<(Target, Matcher)>,
+    schedules: Vec<Schedule>,
+    pub priority: i32,
+    pub sticky: bool,
+}
+
+impl CompiledAutoSwitch {
+    pub fn matches(
+        &self,
+        processes: &[ProcessInfo],
+        focused: Option<&FocusedWindow>,
+        now: NaiveDateTime,
+    ) -> bool {
+        if self.rules.is_empty() && self.schedules.is_empty() {
+            return false;
+        }
+        let candidates: Vec<(&ProcessInfo, Option<&str>)> = match self.trigger {
+            Trigger::Running => processes.iter().map(|p| (p, None)).collect(),
+            Trigger::Focused => focused
+                .and_then(|w| {
+                    let process = processes.iter().find(|p| p.pid == w.pid)?;
+                    Some((process, Some(w.title.as_ref())))
+                })
+                .into_iter()
+                .collect(),
+        };
+        match self.mode {
+            RuleMode::Any => {
+                self.schedules.iter().any(|s| s.is_active(now))
+                    || self
+                        .rules
+                        .iter()
+                        .any(|rule| candidates.iter().any(|&c| rule_matches(rule, c)))
+            }
+            // every process rule has to hold for the same process
+            RuleMode::All => {
+                self.schedules.iter().all(|s| s.is_active(now))
+                    && (self.rules.is_empty()
+                        || candidates
+                            .iter()
+                            .any(|&c| self.rules.iter().all(|rule| rule_matches(rule, c))))
+            }
+        }
+    }
+
+    pub fn uses_schedule(&self) -> bool {
+        !self.schedules.is_empty()
+    }
+
+    pub fn uses_focus(&self) -> bool {
+        self.trigger == Trigger::Focused
+    }
+
+    // whether starting or stopping this process could change the result of `matches`
+    pub fn watches(&self, process: &ProcessInfo) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule_matches(rule, (process, None)))
+    }
+}
+
+fn rule_matches(
+    (target, matcher): &(Target, Matcher),
+    (process, title): (&ProcessInfo, Option<&str>),
+) -> bool {
+    match target {
+        Target::ProcessName => matcher.is_match(&process.name),
+        Target::ExecutablePath => matcher.is_match(&process.exe.to_string_lossy()),
+        Target::CommandLine => {
+            let args = process.cmd.get(1..).unwrap_or(&[]);
+            matcher.is_match(&args.join(" "))
+        }
+        Target::WindowTitle => title.map_or(false, |title| matcher.is_match(title)),
+    }
+}
+
+// Text form used by the profile editor, e.g.
+// `photoshop*.exe | path:C:\Tools\* | args:/--project\s+keypad/ | time:Mon-Fri 09:00-09:30`
+impl Display for AutoSwitch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let separator = match self.mode {
+            RuleMode::Any => " | ",
+            RuleMode::All => " & ",
+        };
+        let rules: Vec<_> = self.rules.iter().map(|r| r.to_string()).collect();
+        write!(f, "{}", rules.join(separator))
+    }
+}
+
+impl FromStr for AutoSwitch {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode, rules) = split_rules(s)?;
+        let rules: Result<Vec<_>, _> = rules.into_iter().map(Rule::from_str).collect();
+        let auto_switch = Self {
+            trigger: Trigger::Running,
+            mode,
+            rules: rules?,
+            priority: 0,
+            sticky: false,
+        };
+        auto_switch.compile()?;
+        Ok(auto_switch)
+    }
+}
+
+// the separators inside a /regex/ or "quoted" pattern are part of the pattern
+fn split_rules(s: &str) -> Result<(RuleMode, Vec<&str>), RuleError> {
+    let mut mode = None;
+    let mut rules = Vec::new();
+    let mut rest = s;
+    loop {
+        let from = quoted_len(rest);
+        let next = SEPARATORS
+            .iter()
+            .filter_map(|&(sep, m)| rest[from..].find(sep).map(|idx| (from + idx, sep, m)))
+            .min_by_key(|&(idx, _, _)| idx);
+        let (idx, separator, next_mode) = match next {
+            Some(next) => next,
+            None => break,
+        };
+        if mode.is_some() && mode != Some(next_mode) {
+            return Err(RuleError::MixedModes);
+        }
+        mode = Some(next_mode);
+        rules.push(&rest[..idx]);
+        rest = &rest[idx + separator.len()..];
+    }
+    rules.push(rest);
+    Ok((mode.unwrap_or_default(), rules))
+}
+
+// how much of the text is the first rule's target and quoted pattern, or 0 when it isn't quoted
+fn quoted_len(text: &str) -> usize {
+    let value = split_target(text)
+        .map_or(text, |(_, value)| value)
+        .trim_start();
+    let start = text.len() - value.len();
+    let quote = match value.chars().next() {
+        Some(quote) if quote == '/' || quote == '"' => quote,
+        _ => return 0,
+    };
+    // the closing quote is the first one that ends the rule
+    value[1..]
+        .match_indices(quote)
+        .map(|(idx, _)| start + idx + 2)
+        .find(|&end| {
+            let after = &text[end..];
+            after.trim().is_empty() || SEPARATORS.iter().any(|(sep, _)| after.starts_with(sep))
+        })
+        .unwrap_or(0)
+}
+
+// only the known targets count, so the colon in a drive or a regex group stays in the pattern
+fn split_target(s: &str) -> Option<(String, &str)> {
+    let idx = s.find(':')?;
+    let target = s[..idx].trim().to_ascii_lowercase();
+    match TARGETS.contains(&target.as_ref()) {
+        true => Some((target, &s[idx + 1..])),
+        false => None,
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rule::ProcessName(p) => write!(f, "{}", p),
+            Rule::ExecutablePath(p) => write!(f, "path:{}", p),
+            Rule::CommandLine(p) => write!(f, "args:{}", p),
+            Rule::WindowTitle(p) => write!(f, "title:{}", p),
+            Rule::Schedule(schedule) => write!(f, "time:{}", schedule),
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let rule = match split_target(s) {
+            Some((target, value)) => match target.as_ref() {
+                "name" => Rule::ProcessName(value.parse()?),
+                "path" => Rule::ExecutablePath(value.parse()?),
+                "args" => Rule::CommandLine(value.parse()?),
+                "title" => Rule::WindowTitle(value.parse()?),
+                _ => Rule::Schedule(value.parse()?),
+            },
+            None => Rule::ProcessName(s.parse()?),
+        };
+        Ok(rule)
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // quoted when it would otherwise read back as something else
+            Pattern::Exact(text) if needs_quotes(text) => write!(f, "\"{}\"", text),
+            Pattern::Exact(text) | Pattern::Glob(text) => write!(f, "{}", text),
+            Pattern::Regex(text) => write!(f, "/{}/", text),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(RuleError::Empty);
+        }
+        let pattern = if s.len() > 1 && s.starts_with('"') && s.ends_with('"') {
+            Pattern::Exact(s[1..s.len() - 1].into())
+        } else if s.len() > 1 && s.starts_with('/') && s.ends_with('/') {
+            Pattern::Regex(s[1..s.len() - 1].into())
+        } else if s.contains(|c| c == '*' || c == '?' || c == '[') {
+            Pattern::Glob(s.into())
+        } else {
+            Pattern::Exact(s.into())
+        };
+        Ok(pattern)
+    }
+}
+
+fn needs_quotes(text: &str) -> bool {
+    text.parse::<Pattern>().ok() != Some(Pattern::Exact(text.into()))
+        || split_target(text).is_some()
+        || SEPARATORS.iter().any(|(sep, _)| text.contains(sep))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(text: &str) -> Vec<Rule> {
+        text.parse::<AutoSwitch>().unwrap().rules
+    }
+
+    #[test]
+    fn rules_round_trip_as_text() {
+        let texts = [
+            r"photoshop*.exe | path:C:\Tools\* | args:/--project\s+keypad/",
+            "/foo(?:bar)/ & title:/a | b/",
+            r#"title:"Notes & more" | "setup?.exe" | "name:x""#,
+            "time:Mon-Fri 09:00-09:30",
+        ];
+        for text in texts.iter() {
+            let auto_switch: AutoSwitch = text.parse().unwrap();
+            assert_eq!(&auto_switch.to_string(), text);
+        }
+
+        let exact = AutoSwitch::process("setup?.exe");
+        assert_eq!(exact.to_string(), r#""setup?.exe""#);
+        assert_eq!(exact.to_string().parse::<AutoSwitch>().unwrap(), exact);
+    }
+
+    #[test]
+    fn patterns_keep_their_separators() {
+        assert_eq!(
+            rules("/foo(?:bar)/"),
+            [Rule::ProcessName(Pattern::Regex("foo(?:bar)".into()))]
+        );
+        assert_eq!(
+            rules(r"title:/a | b/ | C:\Tools\app.exe"),
+            [
+                Rule::WindowTitle(Pattern::Regex("a | b".into())),
+                Rule::ProcessName(Pattern::Exact(r"C:\Tools\app.exe".into())),
+            ]
+        );
+        assert_eq!(
+            rules(r#""a | b" & args:/x/"#),
+            [
+                Rule::ProcessName(Pattern::Exact("a | b".into())),
+                Rule::CommandLine(Pattern::Regex("x".into())),
+            ]
+        );
+        assert!(matches!(
+            "a.exe | b.exe & c.exe".parse::<AutoSwitch>(),
+            Err(RuleError::MixedModes)
+        ));
+        assert!(matches!(" ".parse::<AutoSwitch>(), Err(RuleError::Empty)));
+        assert!(matches!(
+            "args:/(/".parse::<AutoSwitch>(),
+            Err(RuleError::InvalidRegex(_))
+        ));
+    }
+}
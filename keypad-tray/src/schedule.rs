@@ -0,0 +1,227 @@
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::rules::RuleError;
+
+const TIME_FORMAT: &str = "%H:%M";
+
+pub trait Clock {
+    fn now(&self) -> NaiveDateTime;
+}
+
+pub struct LocalClock;
+
+impl Clock for LocalClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone)]
+pub struct FakeClock {
+    now: Arc<Mutex<NaiveDateTime>>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn at(now: &str) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(parse_datetime(now))),
+        }
+    }
+
+    pub fn set(&self, now: &str) {
+        *self.now.lock().unwrap() = parse_datetime(now);
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> NaiveDateTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+fn parse_datetime(now: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(now, "%Y-%m-%d %H:%M").unwrap()
+}
+
+// Active between start and end on the given days, every day when there are none.
+// An end before the start runs past midnight, and equal times cover the whole day.
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct Schedule {
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl Schedule {
+    pub fn is_active(&self, now: NaiveDateTime) -> bool {
+        let (day, time) = (now.weekday(), now.time());
+        if self.start == self.end {
+            self.on(day)
+        } else if self.start < self.end {
+            self.on(day) && time >= self.start && time < self.end
+        } else {
+            // the days are the ones the window starts on
+            (self.on(day) && time >= self.start) || (self.on(day.pred()) && time < self.end)
+        }
+    }
+
+    fn on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+}
+
+// Text form used in auto-switch rules, e.g. `Mon-Fri 09:00-09:30`, `Sat,Sun` or `18:00-08:00`
+impl Display for Schedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if !self.days.is_empty() {
+            parts.push(format_days(&self.days));
+        }
+        if self.start != self.end || self.days.is_empty() {
+            parts.push(format!(
+                "{}-{}",
+                self.start.format(TIME_FORMAT),
+                self.end.format(TIME_FORMAT)
+            ));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || RuleError::InvalidSchedule(s.trim().into());
+        let mut schedule = Schedule {
+            days: Vec::new(),
+            start: NaiveTime::from_hms(0, 0, 0),
+            end: NaiveTime::from_hms(0, 0, 0),
+        };
+        let mut parts = s.split_whitespace().peekable();
+        if parts.peek().is_none() {
+            return Err(RuleError::Empty);
+        }
+        if let Some(days) = parts.peek().filter(|p| !p.contains(':')) {
+            schedule.days = parse_days(days).ok_or_else(invalid)?;
+            parts.next();
+        }
+        if let Some(times) = parts.next() {
+            let mut times = times.splitn(2, '-');
+            let mut time = || -> Option<NaiveTime> {
+                NaiveTime::parse_from_str(times.next()?, TIME_FORMAT).ok()
+            };
+            schedule.start = time().ok_or_else(invalid)?;
+            schedule.end = time().ok_or_else(invalid)?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(schedule)
+    }
+}
+
+fn parse_days(s: &str) -> Option<Vec<Weekday>> {
+    let mut days = Vec::new();
+    for part in s.split(',') {
+        let mut range = part.splitn(2, '-');
+        let first: Weekday = range.next()?.trim().parse().ok()?;
+        let last: Weekday = match range.next() {
+            Some(last) => last.trim().parse().ok()?,
+            None => first,
+        };
+        let mut day = first;
+        loop {
+            if !days.contains(&day) {
+                days.push(day);
+            }
+            if day == last {
+                break;
+            }
+            day = day.succ();
+        }
+    }
+    days.sort_by_key(|d| d.num_days_from_monday());
+    Some(days)
+}
+
+// consecutive runs of three or more days are written as a range
+fn format_days(days: &[Weekday]) -> String {
+    let mut runs: Vec<Vec<Weekday>> = Vec::new();
+    for &day in days {
+        match runs.last_mut() {
+            Some(run) if run.last().map(|d| d.succ()) == Some(day) => run.push(day),
+            _ => runs.push(vec![day]),
+        }
+    }
+    let parts: Vec<String> = runs
+        .iter()
+        .flat_map(|run| match run.len() {
+            1 | 2 => run.iter().map(|d| format!("{:?}", d)).collect(),
+            _ => vec![format!("{:?}-{:?}", run[0], run[run.len() - 1])],
+        })
+        .collect();
+    parts.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn active(schedule: &str, now: &str) -> bool {
+        let schedule: Schedule = schedule.parse().unwrap();
+        schedule.is_active(parse_datetime(now))
+    }
+
+    #[test]
+    fn schedules_round_trip_as_text() {
+        for text in &[
+            "Mon-Fri 09:00-09:30",
+            "Sat,Sun",
+            "18:00-08:00",
+            "Mon,Wed-Fri 12:00-13:00",
+        ] {
+            let schedule: Schedule = text.parse().unwrap();
+            assert_eq!(&schedule.to_string(), text);
+        }
+        assert_eq!(
+            "mon-wed,tue".parse::<Schedule>().unwrap().to_string(),
+            "Mon-Wed"
+        );
+        assert!("Mon-Fri 9am".parse::<Schedule>().is_err());
+        assert!("Someday".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn weekday_window_is_active() {
+        // 2021-03-01 was a Monday
+        assert!(active("Mon-Fri 09:00-09:30", "2021-03-01 09:00"));
+        assert!(active("Mon-Fri 09:00-09:30", "2021-03-05 09:29"));
+        assert!(!active("Mon-Fri 09:00-09:30", "2021-03-01 09:30"));
+        assert!(!active("Mon-Fri 09:00-09:30", "2021-03-06 09:15"));
+        assert!(active("Sat,Sun", "2021-03-07 23:59"));
+        assert!(!active("Sat,Sun", "2021-03-08 00:00"));
+    }
+
+    #[test]
+    fn overnight_window_belongs_to_its_start_day() {
+        assert!(active("Fri 18:00-08:00", "2021-03-05 23:00"));
+        assert!(active("Fri 18:00-08:00", "2021-03-06 07:59"));
+        assert!(!active("Fri 18:00-08:00", "2021-03-05 07:59"));
+        assert!(active("18:00-08:00", "2021-03-01 03:00"));
+        assert!(!active("18:00-08:00", "2021-03-01 12:00"));
+    }
+}
@@ -1,14 +1,33 @@
 use std::{
-    fs::{create_dir_all, File},
-    io::{prelude::*, BufReader},
-    path::PathBuf,
+    cell::Cell,
+    collections::{hash_map::DefaultHasher, HashMap},
+    env,
+    fs::{copy, create_dir_all, read, read_dir, read_to_string, remove_file, rename, File},
+    hash::{Hash, Hasher},
+    io::prelude::*,
+    path::{Path, PathBuf},
+    sync::mpsc::channel,
+    thread::spawn,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use app_dirs::{get_app_dir, AppDataType, AppDirsError, AppInfo};
-use serde_json::{from_reader, to_writer_pretty};
+use keypad::{
+    cheat_sheet_page_path, render_cheat_sheet, Board, ParseComboError, ProfileError, ProfileKeys,
+    PROFILES_VERSION,
+};
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{de::Error as _, Deserialize, Serialize};
+use serde_json::{from_value, json, to_value, Value};
 use thiserror::Error;
+use uuid::Uuid;
 
-use crate::models::Profile;
+use crate::{
+    config::{profiles_dir_setting, value_from_args, ConfigError},
+    format::ProfileFormat,
+    models::{validate_profiles, Profile},
+    rules::AutoSwitch,
+};
 
 const APP_INFO: AppInfo = AppInfo {
     name: "KeypadControl",
@@ -16,6 +35,17 @@ const APP_INFO: AppInfo = AppInfo {
 };
 const PROFILES_DIR: &str = "profiles";
 const PROFILES_FILE: &str = "profiles.json";
+const TOML_PROFILES_FILE: &str = "profiles.toml";
+const BACKUPS_DIR: &str = "backups";
+const MAX_BACKUPS: usize = 5;
+const PROFILES_DIR_FLAG: &str = "--profiles-dir";
+const PROFILES_DIR_VAR: &str = "KEYPAD_PROFILES_DIR";
+const WATCH_DEBOUNCE_MILLIS: u64 = 500;
+
+type Migration = fn(Value) -> Result<Value, StoreError>;
+
+// MIGRATIONS[n] upgrades a document from version n to version n + 1
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1, migrate_v1_to_v2, migrate_v2_to_v3];
 
 #[derive(Error, Debug)]
 pub enum StoreError {
@@ -25,37 +55,802 @@ pub enum StoreError {
     IoError(#[from] std::io::Error),
     #[error("Serde JSON error: {0}")]
     SerdeError(#[from] serde_json::error::Error),
+    #[error("TOML parse error: {0}")]
+    TomlError(#[from] toml::de::Error),
+    #[error("TOML write error: {0}")]
+    TomlWriteError(#[from] toml::ser::Error),
+    #[error("Invalid key combo: {0}")]
+    InvalidCombo(#[from] ParseComboError),
+    #[error("File watch error: {0}")]
+    WatchError(#[from] notify::Error),
+    #[error("Profiles were changed by another program since they were loaded")]
+    Conflict,
+    #[error("Unsupported profile file version: {0}")]
+    UnsupportedVersion(u64),
+    #[error("Invalid profile: {0}")]
+    InvalidProfile(#[from] ProfileError),
+    #[error("Config error: {0}")]
+    ConfigError(#[from] ConfigError),
+    #[error("Profiles file is corrupt, it was backed up to {}: {source}", backup.display())]
+    CorruptProfiles {
+        backup: PathBuf,
+        source: Box<StoreError>,
+    },
+    #[error("Profiles file can't be used, it was backed up to {}: {source}", backup.display())]
+    RejectedProfiles {
+        backup: PathBuf,
+        source: Box<StoreError>,
+    },
+}
+
+impl StoreError {
+    fn is_parse_error(&self) -> bool {
+        match self {
+            StoreError::SerdeError(_) | StoreError::TomlError(_) | StoreError::InvalidCombo(_) => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ConflictResolution {
+    Rename,
+    Overwrite,
+    Skip,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ProfileBundle {
+    version: u32,
+    profiles: Vec<Profile>,
+    // some profiles had no id and were given a new one
+    #[serde(skip)]
+    generated_ids: bool,
+}
+
+pub trait ProfileStore {
+    fn load(&self) -> Result<Vec<Profile>, StoreError>;
+    fn store(&self, profiles: &[Profile]) -> Result<(), StoreError>;
+
+    fn overwrite(&self, profiles: &[Profile]) -> Result<(), StoreError> {
+        self.store(profiles)
+    }
+
+    fn has_external_changes(&self) -> Result<bool, StoreError> {
+        Ok(false)
+    }
+
+    fn watch(&self, _on_change: Box<dyn Fn() + Send>) -> Result<Option<StoreWatcher>, StoreError> {
+        Ok(None)
+    }
+}
+
+pub struct StoreWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+pub struct FileStore {
+    dir: PathBuf,
+    known_hash: Cell<Option<u64>>,
+}
+
+impl FileStore {
+    pub fn new(dir: PathBuf) -> Result<Self, StoreError> {
+        create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            known_hash: Cell::new(None),
+        })
+    }
+
+    pub fn locate() -> Result<Self, StoreError> {
+        let dir = match value_from_args(env::args(), PROFILES_DIR_FLAG) {
+            Some(dir) => dir.into(),
+            None => match env::var_os(PROFILES_DIR_VAR) {
+                Some(dir) => dir.into(),
+                None => match profiles_dir_setting()? {
+                    Some(dir) => dir,
+                    None => get_app_dir(AppDataType::UserConfig, &APP_INFO, PROFILES_DIR)?,
+                },
+            },
+        };
+        log::info!("Using profiles directory {}", dir.display());
+        Self::new(dir)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn list_backups(&self) -> Result<Vec<PathBuf>, StoreError> {
+        let backups_dir = self.dir.join(BACKUPS_DIR);
+        if !backups_dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut backups = Vec::new();
+        for entry in read_dir(&backups_dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "json" || ext == "toml") {
+                backups.push(path);
+            }
+        }
+        // names embed a fixed-width timestamp, so newest sorts first when reversed
+        backups.sort();
+        backups.reverse();
+        Ok(backups)
+    }
+
+    pub fn restore_backup(&self, backup: &Path) -> Result<Vec<Profile>, StoreError> {
+        let profiles = read_bundle(backup)?.profiles;
+        self.overwrite(&profiles)?;
+        Ok(profiles)
+    }
+
+    fn current_hash(&self) -> Result<Option<u64>, StoreError> {
+        let path = self.profiles_file();
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(content_hash(&read(path)?)))
+    }
+
+    fn profiles_file(&self) -> PathBuf {
+        let toml = self.dir.join(TOML_PROFILES_FILE);
+        if toml.exists() {
+            toml
+        } else {
+            self.dir.join(PROFILES_FILE)
+        }
+    }
+
+    fn rotate_backups(&self, path: &Path) -> Result<(), StoreError> {
+        let backups_dir = self.dir.join(BACKUPS_DIR);
+        create_dir_all(&backups_dir)?;
+
+        let mut timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let extension = ProfileFormat::from_path(path).extension();
+        let mut backup = backups_dir.join(format!("profiles-{:015}.{}", timestamp, extension));
+        while backup.exists() {
+            timestamp += 1;
+            backup = backups_dir.join(format!("profiles-{:015}.{}", timestamp, extension));
+        }
+        copy(path, &backup)?;
+
+        for old in self.list_backups()?.into_iter().skip(MAX_BACKUPS) {
+            remove_file(old)?;
+        }
+        Ok(())
+    }
 }
 
-pub fn load_profiles() -> Result<Vec<Profile>, StoreError> {
-    let path = get_or_create_profiles_file()?;
-    let file = File::open(&path)?;
-    let reader = BufReader::new(file);
-    let profiles = match from_reader(reader) {
-        Ok(profiles) => profiles,
-        Err(_) => {
-            let profiles = Vec::new();
-            store_profiles(&profiles)?;
-            profiles
+impl ProfileStore for FileStore {
+    fn load(&self) -> Result<Vec<Profile>, StoreError> {
+        let path = self.profiles_file();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let contents = read_to_string(&path)?;
+        self.known_hash.set(Some(content_hash(contents.as_bytes())));
+        if contents.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+
+        match parse_bundle(&contents, ProfileFormat::from_path(&path)) {
+            Ok(bundle) => {
+                // generated ids are only stable once they're saved
+                if bundle.generated_ids {
+                    if let Err(e) = self.overwrite(&bundle.profiles) {
+                        log::warn!("Unable to save generated profile ids: {}", e);
+                    }
+                }
+                Ok(bundle.profiles)
+            }
+            // the next save replaces the file, so anything that can't be loaded is kept aside
+            Err(e) if e.is_parse_error() => {
+                let backup = backup_unloadable_file(&path, "corrupt")?;
+                Err(StoreError::CorruptProfiles {
+                    backup,
+                    source: Box::new(e),
+                })
+            }
+            Err(e) => {
+                let backup = backup_unloadable_file(&path, "rejected")?;
+                Err(StoreError::RejectedProfiles {
+                    backup,
+                    source: Box::new(e),
+                })
+            }
+        }
+    }
+
+    fn store(&self, profiles: &[Profile]) -> Result<(), StoreError> {
+        if self.has_external_changes()? {
+            return Err(StoreError::Conflict);
+        }
+        self.overwrite(profiles)
+    }
+
+    fn overwrite(&self, profiles: &[Profile]) -> Result<(), StoreError> {
+        let path = self.profiles_file();
+        if path.exists() {
+            self.rotate_backups(&path)?;
+        }
+        write_bundle(&path, profiles)?;
+        self.known_hash.set(self.current_hash()?);
+        Ok(())
+    }
+
+    fn has_external_changes(&self) -> Result<bool, StoreError> {
+        // nothing has been loaded yet, so there is nothing to conflict with
+        if self.known_hash.get().is_none() {
+            return Ok(false);
         }
+        Ok(self.current_hash()? != self.known_hash.get())
+    }
+
+    fn watch(&self, on_change: Box<dyn Fn() + Send>) -> Result<Option<StoreWatcher>, StoreError> {
+        let (tx, rx) = channel();
+        let mut watcher = watcher(tx, Duration::from_millis(WATCH_DEBOUNCE_MILLIS))?;
+        // watch the directory, atomic saves replace the file rather than modifying it
+        watcher.watch(&self.dir, RecursiveMode::NonRecursive)?;
+
+        spawn(move || {
+            for event in rx {
+                let path = match event {
+                    DebouncedEvent::Create(path)
+                    | DebouncedEvent::Write(path)
+                    | DebouncedEvent::Remove(path)
+                    | DebouncedEvent::Rename(_, path) => path,
+                    _ => continue,
+                };
+                let name = path.file_name().and_then(|n| n.to_str());
+                if name == Some(PROFILES_FILE) || name == Some(TOML_PROFILES_FILE) {
+                    on_change();
+                }
+            }
+        });
+
+        Ok(Some(StoreWatcher { _watcher: watcher }))
+    }
+}
+
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore {
+    profiles: std::cell::RefCell<Vec<Profile>>,
+}
+
+#[cfg(test)]
+impl ProfileStore for MemoryStore {
+    fn load(&self) -> Result<Vec<Profile>, StoreError> {
+        Ok(self.profiles.borrow().clone())
+    }
+
+    fn store(&self, profiles: &[Profile]) -> Result<(), StoreError> {
+        *self.profiles.borrow_mut() = profiles.to_vec();
+        Ok(())
+    }
+}
+
+pub fn export_profiles(path: &Path, profiles: &[Profile]) -> Result<(), StoreError> {
+    write_bundle(path, profiles)
+}
+
+pub fn export_cheat_sheet(
+    path: &Path,
+    board: &Board,
+    profiles: &[Profile],
+) -> Result<(), StoreError> {
+    let entries = profiles
+        .iter()
+        .map(|p| p.cheat_sheet_entry(profiles))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (page, svg) in render_cheat_sheet(board, &entries).iter().enumerate() {
+        write_atomic(&cheat_sheet_page_path(path, page), |file| {
+            file.write_all(svg.as_bytes())?;
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+pub fn import_profiles(path: &Path) -> Result<Vec<Profile>, StoreError> {
+    Ok(read_bundle(path)?.profiles)
+}
+
+pub fn has_name_conflicts(existing: &[Profile], imported: &[Profile]) -> bool {
+    imported
+        .iter()
+        .any(|i| existing.iter().any(|e| e.name == i.name))
+}
+
+pub fn merge_profiles(
+    existing: &mut Vec<Profile>,
+    imported: Vec<Profile>,
+    resolution: ConflictResolution,
+) -> usize {
+    // where each imported id ended up, so children keep inheriting from the same profile
+    let mut ids = HashMap::new();
+    let mut added = Vec::new();
+    for (original, mut profile) in imported.iter().cloned().enumerate() {
+        let imported_id = profile.id;
+        // an overwritten profile keeps its id, copies get a new one
+        if existing.iter().any(|p| p.id == profile.id) {
+            profile.id = Uuid::new_v4();
+        }
+        let idx = match existing.iter().position(|p| p.name == profile.name) {
+            None => {
+                existing.push(profile);
+                existing.len() - 1
+            }
+            Some(idx) => match resolution {
+                // children of a skipped profile inherit from the one it would have replaced
+                ConflictResolution::Skip => {
+                    ids.insert(imported_id, existing[idx].id);
+                    continue;
+                }
+                ConflictResolution::Overwrite => {
+                    profile.id = existing[idx].id;
+                    existing[idx] = profile;
+                    idx
+                }
+                ConflictResolution::Rename => {
+                    profile.name = unique_name(existing, &profile.name);
+                    existing.push(profile);
+                    existing.len() - 1
+                }
+            },
+        };
+        ids.insert(imported_id, existing[idx].id);
+        added.push((idx, original));
+    }
+
+    for &(idx, _) in added.iter() {
+        if let Some(parent) = existing[idx].parent {
+            existing[idx].parent = Some(*ids.get(&parent).unwrap_or(&parent));
+        }
+    }
+    // a cycle through a skipped profile is flattened instead, a parent that's nowhere is dropped
+    for &(idx, original) in added.iter() {
+        if existing[idx].resolve(existing).is_ok() {
+            continue;
+        }
+        let flat = imported[original]
+            .flattened(&imported)
+            .unwrap_or_else(|_| Profile {
+                parent: None,
+                inherited: [false; 6],
+                ..imported[original].clone()
+            });
+        existing[idx] = Profile {
+            id: existing[idx].id,
+            name: existing[idx].name.clone(),
+            ..flat
+        };
+    }
+    added.len()
+}
+
+fn write_bundle(path: &Path, profiles: &[Profile]) -> Result<(), StoreError> {
+    validate_profiles(profiles)?;
+    let bundle = ProfileBundle {
+        version: PROFILES_VERSION,
+        profiles: profiles.to_vec(),
+        generated_ids: false,
     };
-    Ok(profiles)
+    let contents = ProfileFormat::from_path(path).write(&to_value(&bundle)?)?;
+    write_atomic(path, |file| {
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    })
 }
 
-pub fn store_profiles(profiles: &Vec<Profile>) -> Result<(), StoreError> {
-    let path = get_or_create_profiles_file()?;
-    let mut file = File::create(&path)?;
-    to_writer_pretty(&file, &profiles)?;
-    file.flush()?;
+fn write_atomic<F>(path: &Path, write: F) -> Result<(), StoreError>
+where
+    F: FnOnce(&mut File) -> Result<(), StoreError>,
+{
+    let tmp = temp_path(path);
+    let result = File::create(&tmp)
+        .map_err(StoreError::from)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.flush()?;
+            file.sync_all()?;
+            Ok(())
+        });
+    if let Err(e) = result {
+        let _ = remove_file(&tmp);
+        return Err(e);
+    }
+    rename(&tmp, path)?;
     Ok(())
 }
 
-fn get_or_create_profiles_file() -> Result<PathBuf, StoreError> {
-    let app_dir = get_app_dir(AppDataType::UserConfig, &APP_INFO, PROFILES_DIR)?;
-    create_dir_all(&app_dir)?;
-    let path = app_dir.join(&PROFILES_FILE);
-    if !path.exists() {
-        let _ = File::create(&path)?;
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+fn read_bundle(path: &Path) -> Result<ProfileBundle, StoreError> {
+    let contents = read_to_string(path)?;
+    parse_bundle(&contents, ProfileFormat::from_path(path))
+}
+
+fn parse_bundle(contents: &str, format: ProfileFormat) -> Result<ProfileBundle, StoreError> {
+    let mut document = format.parse(contents)?;
+    let mut version = document_version(&document)?;
+    if version > PROFILES_VERSION as u64 {
+        return Err(StoreError::UnsupportedVersion(version));
+    }
+
+    while version < PROFILES_VERSION as u64 {
+        log::info!("Migrating profiles from version {}", version);
+        document = MIGRATIONS[version as usize](document)?;
+        version += 1;
+    }
+
+    let generated_ids = has_missing_ids(&document);
+    let mut bundle: ProfileBundle = from_value(document)?;
+    bundle.generated_ids = generated_ids;
+    validate_profiles(&bundle.profiles)?;
+    Ok(bundle)
+}
+
+fn has_missing_ids(document: &Value) -> bool {
+    document["profiles"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|profile| profile.get("id").is_none())
+}
+
+fn document_version(document: &Value) -> Result<u64, StoreError> {
+    match document {
+        Value::Array(_) => Ok(0),
+        Value::Object(map) => map
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| serde_json::Error::custom("missing or invalid schema version").into()),
+        _ => Err(serde_json::Error::custom("expected a profile list").into()),
+    }
+}
+
+fn migrate_v0_to_v1(document: Value) -> Result<Value, StoreError> {
+    Ok(json!({
+        "version": 1,
+        "profiles": document,
+    }))
+}
+
+// auto_launch_program became an auto_switch rule set
+fn migrate_v1_to_v2(mut document: Value) -> Result<Value, StoreError> {
+    document["version"] = json!(2);
+    if let Some(profiles) = document["profiles"].as_array_mut() {
+        for profile in profiles.iter_mut().filter_map(Value::as_object_mut) {
+            let program = profile.remove("auto_launch_program");
+            let auto_switch = match program.as_ref().and_then(Value::as_str) {
+                Some(name) if name.trim().len() > 0 => to_value(AutoSwitch::process(name.trim()))?,
+                _ => Value::Null,
+            };
+            profile.insert("auto_switch".into(), auto_switch);
+        }
+    }
+    Ok(document)
+}
+
+// combos can be null for unassigned keys, which older versions can't read
+fn migrate_v2_to_v3(mut document: Value) -> Result<Value, StoreError> {
+    document["version"] = json!(3);
+    Ok(document)
+}
+
+fn backup_unloadable_file(path: &Path, reason: &str) -> Result<PathBuf, StoreError> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let extension = ProfileFormat::from_path(path).extension();
+    let name = format!("profiles.{}-{}.{}", reason, timestamp, extension);
+    let backup = path.with_file_name(name);
+    copy(path, &backup)?;
+    log::warn!("Unloadable profiles file backed up to {}", backup.display());
+    Ok(backup)
+}
+
+fn content_hash(contents: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn unique_name(existing: &[Profile], name: &str) -> String {
+    (2..)
+        .map(|n| format!("{} ({})", name, n))
+        .find(|candidate| existing.iter().all(|p| &p.name != candidate))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::write, io};
+
+    use super::*;
+
+    fn test_store(name: &str) -> FileStore {
+        let dir = env::temp_dir().join(format!("keypad-store-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        FileStore::new(dir).unwrap()
+    }
+
+    fn profile(name: &str) -> Profile {
+        Profile {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    fn names(profiles: &[Profile]) -> Vec<&str> {
+        profiles.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[test]
+    fn interrupted_write_keeps_previous_profiles() {
+        let store = test_store("interrupted");
+        store.store(&[profile("IDE"), profile("OBS")]).unwrap();
+
+        let path = store.profiles_file();
+        let result = write_atomic(&path, |file| {
+            file.write_all(b"{\"version\": 1, \"profi")?;
+            Err(io::Error::new(io::ErrorKind::Other, "power lost").into())
+        });
+
+        assert!(result.is_err());
+        assert!(!temp_path(&path).exists());
+        let loaded = store.load().unwrap();
+        assert_eq!(names(&loaded), ["IDE", "OBS"]);
+    }
+
+    #[test]
+    fn leftover_temp_file_is_ignored() {
+        let store = test_store("leftover");
+        store.store(&[profile("IDE")]).unwrap();
+        write(temp_path(&store.profiles_file()), "[{\"name\": \"half").unwrap();
+
+        let loaded = store.load().unwrap();
+        assert_eq!(names(&loaded), ["IDE"]);
+
+        store.store(&[profile("OBS")]).unwrap();
+        let loaded = store.load().unwrap();
+        assert_eq!(names(&loaded), ["OBS"]);
+    }
+
+    #[test]
+    fn backups_are_limited() {
+        let store = test_store("rotation");
+        for i in 0..MAX_BACKUPS + 3 {
+            store.store(&[profile(&format!("Profile {}", i))]).unwrap();
+        }
+
+        let backups = store.list_backups().unwrap();
+        assert_eq!(backups.len(), MAX_BACKUPS);
+        let newest = read_bundle(&backups[0]).unwrap();
+        assert_eq!(names(&newest.profiles), [format!("Profile {}", MAX_BACKUPS + 1)]);
+    }
+
+    #[test]
+    fn restore_backup_replaces_profiles() {
+        let store = test_store("restore");
+        store.store(&[profile("IDE")]).unwrap();
+        store.store(&[profile("Photoshop")]).unwrap();
+
+        let backups = store.list_backups().unwrap();
+        let restored = store.restore_backup(&backups[0]).unwrap();
+
+        assert_eq!(names(&restored), ["IDE"]);
+        assert_eq!(names(&store.load().unwrap()), ["IDE"]);
+    }
+
+    #[test]
+    fn corrupt_file_is_backed_up() {
+        let store = test_store("corrupt");
+        write(store.profiles_file(), "[{\"name\": ").unwrap();
+
+        match store.load() {
+            Err(StoreError::CorruptProfiles { backup, .. }) => assert!(backup.exists()),
+            other => panic!("expected corrupt profiles error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn newer_files_are_backed_up() {
+        let store = test_store("newer");
+        let newer = json!({ "version": PROFILES_VERSION + 1, "profiles": [] });
+        write(store.profiles_file(), newer.to_string()).unwrap();
+
+        let backup = match store.load() {
+            Err(StoreError::RejectedProfiles { backup, source }) => {
+                assert!(matches!(*source, StoreError::UnsupportedVersion(_)));
+                backup
+            }
+            other => panic!("expected rejected profiles error, got {:?}", other),
+        };
+        store.store(&[profile("IDE")]).unwrap();
+        assert_eq!(read_to_string(backup).unwrap(), newer.to_string());
+    }
+
+    #[test]
+    fn toml_profiles_use_combo_strings() {
+        let store = test_store("toml");
+        write(store.dir.join(TOML_PROFILES_FILE), "").unwrap();
+        let mut ide = profile("IDE");
+        ide.combos[0] = Some("Ctrl+Shift+F5, Enter".parse().unwrap());
+        ide.labels[0].label = Some("Restart debugging".into());
+        store.store(&[ide.clone()]).unwrap();
+
+        let contents = read_to_string(store.dir.join(TOML_PROFILES_FILE)).unwrap();
+        assert!(contents.contains("Ctrl + Shift + F5, Enter"));
+        assert!(contents.contains("'None'"));
+        assert!(!store.dir.join(PROFILES_FILE).exists());
+
+        let loaded = store.load().unwrap();
+        assert_eq!(names(&loaded), ["IDE"]);
+        assert_eq!(loaded[0].combos, ide.combos);
+        assert_eq!(loaded[0].labels, ide.labels);
+    }
+
+    #[test]
+    fn cheat_sheet_shows_inherited_keys() {
+        let store = test_store("cheat-sheet");
+        let mut base = profile("Default");
+        base.combos[0] = Some("Ctrl + C".parse().unwrap());
+        let mut ide = profile("IDE");
+        ide.parent = Some(base.id);
+        ide.inherited[0] = true;
+        let path = store.dir.join("keys.svg");
+        export_cheat_sheet(&path, &Board::default(), &[base, ide]).unwrap();
+
+        let svg = read_to_string(&path).unwrap();
+        assert!(svg.contains(">IDE</text>"));
+        assert_eq!(svg.matches(">Ctrl + C</text>").count(), 2);
+    }
+
+    #[test]
+    fn saved_profiles_read_the_same_without_the_tray() {
+        let store = test_store("stored-profiles");
+        let mut base = profile("Default");
+        base.combos[0] = Some("Ctrl + C".parse().unwrap());
+        base.labels[0].label = Some("Copy".into());
+        let mut ide = profile("IDE");
+        ide.parent = Some(base.id);
+        ide.inherited[0] = true;
+        ide.combos[1] = Some("F5".parse().unwrap());
+        let profiles = [base, ide];
+        store.store(&profiles).unwrap();
+
+        let document: Value =
+            serde_json::from_str(&read_to_string(store.profiles_file()).unwrap()).unwrap();
+        assert_eq!(document["version"], json!(PROFILES_VERSION));
+        let stored: Vec<keypad::StoredProfile> = from_value(document["profiles"].clone()).unwrap();
+        assert_eq!(stored[1].id, profiles[1].id);
+        assert_eq!(
+            stored[1].resolve(&stored).unwrap(),
+            profiles[1].resolve(&profiles).unwrap()
+        );
+        assert_eq!(
+            stored[1].resolve_labels(&stored).unwrap(),
+            profiles[1].resolve_labels(&profiles).unwrap()
+        );
+    }
+
+    #[test]
+    fn imported_children_follow_their_parent() {
+        let mut base = profile("Default");
+        base.combos[0] = Some("Ctrl + C".parse().unwrap());
+        let mut ide = profile("IDE");
+        ide.parent = Some(base.id);
+        ide.inherited[0] = true;
+        let imported = vec![base.clone(), ide];
+
+        let mut existing = vec![base.clone()];
+        assert_eq!(
+            merge_profiles(&mut existing, imported.clone(), ConflictResolution::Rename),
+            2
+        );
+        assert_eq!(names(&existing), ["Default", "Default (2)", "IDE"]);
+        assert_ne!(existing[1].id, base.id);
+        assert_eq!(existing[2].parent, Some(existing[1].id));
+        assert!(validate_profiles(&existing).is_ok());
+
+        let mut existing = vec![profile("Default")];
+        merge_profiles(&mut existing, imported.clone(), ConflictResolution::Skip);
+        assert_eq!(existing[1].parent, Some(existing[0].id));
+        assert_eq!(existing[1].resolve(&existing).unwrap()[0], None);
+
+        // a parent that's already there is kept, one that's nowhere is dropped
+        let child = imported[1..].to_vec();
+        let mut existing = vec![base.clone()];
+        merge_profiles(&mut existing, child.clone(), ConflictResolution::Skip);
+        assert_eq!(existing[1].parent, Some(base.id));
+        let mut existing = Vec::new();
+        merge_profiles(&mut existing, child, ConflictResolution::Skip);
+        assert_eq!(existing[0].parent, None);
+        assert!(validate_profiles(&existing).is_ok());
+    }
+
+    #[test]
+    fn external_edit_is_detected_on_save() {
+        let store = test_store("conflict");
+        store.store(&[profile("IDE")]).unwrap();
+        assert!(store.load().is_ok());
+        assert!(!store.has_external_changes().unwrap());
+
+        let synced = FileStore::new(store.dir.clone()).unwrap();
+        synced.store(&[profile("Synced")]).unwrap();
+        assert!(store.has_external_changes().unwrap());
+
+        match store.store(&[profile("Local")]) {
+            Err(StoreError::Conflict) => {}
+            other => panic!("expected conflict, got {:?}", other),
+        }
+        assert_eq!(names(&synced.load().unwrap()), ["Synced"]);
+
+        store.overwrite(&[profile("Local")]).unwrap();
+        assert!(!store.has_external_changes().unwrap());
+        assert_eq!(names(&store.load().unwrap()), ["Local"]);
+    }
+
+    #[test]
+    fn legacy_profiles_are_migrated() {
+        let store = test_store("migration");
+        let legacy = json!([
+            { "name": "Default", "combos": combos_json(), "auto_launch_program": null },
+            { "name": "OBS", "combos": combos_json(), "auto_launch_program": "obs64.exe" },
+        ]);
+        write(store.profiles_file(), legacy.to_string()).unwrap();
+
+        let loaded = store.load().unwrap();
+        assert_eq!(names(&loaded), ["Default", "OBS"]);
+        assert_eq!(loaded[0].auto_switch, None);
+        assert_eq!(loaded[1].auto_switch, Some(AutoSwitch::process("obs64.exe")));
+        assert_ne!(loaded[0].id, loaded[1].id);
+
+        let reloaded = store.load().unwrap();
+        assert_eq!(reloaded[0].id, loaded[0].id);
+    }
+
+    fn combos_json() -> Value {
+        to_value(&Profile::default().combos).unwrap()
+    }
+
+    #[test]
+    fn memory_store_round_trips() {
+        let store: Box<dyn ProfileStore> = Box::new(MemoryStore::default());
+        assert!(store.load().unwrap().is_empty());
+
+        store.store(&[profile("IDE")]).unwrap();
+        assert_eq!(names(&store.load().unwrap()), ["IDE"]);
+    }
+
+    #[test]
+    fn profiles_dir_is_read_from_args() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>().into_iter();
+
+        let profiles_dir = |a: &[&str]| value_from_args(args(a), PROFILES_DIR_FLAG);
+
+        assert_eq!(profiles_dir(&["keypad-tray"]), None);
+        assert_eq!(
+            profiles_dir(&["keypad-tray", "--profiles-dir", "D:\\Sync"]),
+            Some("D:\\Sync".into())
+        );
+        assert_eq!(
+            profiles_dir(&["keypad-tray", "--profiles-dir=portable"]),
+            Some("portable".into())
+        );
     }
-    Ok(path)
 }
@@ -1,7 +1,9 @@
 use std::{
     cell::{Cell, RefCell},
     rc::Rc,
+    sync::Arc,
     thread,
+    time::Duration,
 };
 
 use native_windows_derive as nwd;
@@ -9,11 +11,20 @@ use native_windows_gui as nwg;
 use nwd::NwgUi;
 use nwg::NativeUi;
 
-use crate::{control_panel::ControlPanel, models::Profile, store, watchdog::WatchDog};
+use crate::{
+    audit::{AuditLog, Event, SwitchReason},
+    control_panel::ControlPanel,
+    models::Profile,
+    processes::{poll_interval, system_process_source},
+    store::{ProfileStore, StoreError, StoreWatcher},
+    watchdog::{Command, SharedSwitchState, WatchDog},
+};
 
 use keypad::*;
 
 const ICON: &[u8] = include_bytes!("../resources/keycap.ico");
+const RECENT_ACTIVITY_COUNT: usize = 20;
+const PIN_DURATION: Duration = Duration::from_secs(60 * 60);
 
 #[derive(Default, NwgUi)]
 pub struct KeypadTray {
@@ -34,6 +45,18 @@ pub struct KeypadTray {
     #[nwg_control(parent: tray_menu, text: "Profiles")]
     profiles_menu: nwg::Menu,
 
+    #[nwg_control(parent: tray_menu, text: "Pause auto-switch")]
+    #[nwg_events(OnMenuItemSelected: [KeypadTray::toggle_auto_switch])]
+    pause_auto_switch: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Keep this profile for an hour")]
+    #[nwg_events(OnMenuItemSelected: [KeypadTray::pin_profile])]
+    pin_profile: nwg::MenuItem,
+
+    #[nwg_control(parent: tray_menu, text: "Let auto-switch choose")]
+    #[nwg_events(OnMenuItemSelected: [KeypadTray::clear_override])]
+    clear_override: nwg::MenuItem,
+
     #[nwg_control(parent: tray_menu)]
     top_separator: nwg::MenuSeparator,
 
@@ -41,6 +64,10 @@ pub struct KeypadTray {
     #[nwg_events(OnMenuItemSelected: [KeypadTray::open_control_panel])]
     control_panel: nwg::MenuItem,
 
+    #[nwg_control(parent: tray_menu, text: "Recent Activity")]
+    #[nwg_events(OnMenuItemSelected: [KeypadTray::show_recent_activity])]
+    recent_activity: nwg::MenuItem,
+
     #[nwg_control(parent: tray_menu)]
     exit_separator: nwg::MenuSeparator,
 
@@ -60,26 +87,129 @@ pub struct KeypadTray {
 
     profiles: RefCell<Vec<Profile>>,
 
+    store: Option<Rc<dyn ProfileStore>>,
+
+    store_watcher: RefCell<Option<StoreWatcher>>,
+
+    audit: Option<AuditLog>,
+
+    #[nwg_control]
+    #[nwg_events( OnNotice: [KeypadTray::profiles_changed_on_disk] )]
+    store_notice: nwg::Notice,
+
     watchdog: RefCell<Option<WatchDog>>,
 
+    switch_state: SharedSwitchState,
+
     #[nwg_control]
     #[nwg_events( OnNotice: [KeypadTray::watchdog_notice_received] )]
     watchdog_notice: nwg::Notice,
 
+    board: Board,
+
     pub restart_tray: Cell<bool>,
 }
 
 impl KeypadTray {
+    pub fn new(
+        store: Rc<dyn ProfileStore>,
+        audit: AuditLog,
+        switch_state: SharedSwitchState,
+        board: Board,
+    ) -> Self {
+        Self {
+            store: Some(store),
+            audit: Some(audit),
+            switch_state,
+            board,
+            ..Default::default()
+        }
+    }
+
     fn on_init(tray_rc: &Rc<KeypadTray>) {
         KeypadTray::setup_profile_handler(tray_rc);
         KeypadTray::load_profiles(tray_rc);
+        KeypadTray::watch_store(tray_rc);
         KeypadTray::start_watchdog(tray_rc);
+
+        let paused = tray_rc.switch_state.lock().unwrap().paused;
+        tray_rc.pause_auto_switch.set_checked(paused);
+    }
+
+    fn watch_store(&self) {
+        let notice = self.store_notice.sender();
+        match self.store().watch(Box::new(move || notice.notice())) {
+            Ok(watcher) => *self.store_watcher.borrow_mut() = watcher,
+            Err(e) => {
+                let flags =
+                    nwg::TrayNotificationFlags::USER_ICON | nwg::TrayNotificationFlags::LARGE_ICON;
+                self.tray.show(
+                    &format!("{}", e),
+                    Some("Unable to watch profiles for changes"),
+                    Some(flags),
+                    Some(&self.icon),
+                );
+            }
+        }
+    }
+
+    fn profiles_changed_on_disk(&self) {
+        // an open control panel will detect the conflict when it saves
+        if self.editor_data.borrow().is_some() {
+            return;
+        }
+        // our own saves trigger the watcher too, only reload when the content is unexpected
+        match self.store().has_external_changes() {
+            Ok(true) => {}
+            _ => return,
+        }
+        self.reload_profiles();
+    }
+
+    fn reload_profiles(&self) {
+        let flags = nwg::TrayNotificationFlags::USER_ICON | nwg::TrayNotificationFlags::LARGE_ICON;
+        match self.store().load() {
+            Ok(profiles) => {
+                self.tray.show(
+                    "Profiles were changed outside of Keypad and have been reloaded",
+                    Some("Profiles reloaded"),
+                    Some(flags),
+                    Some(&self.icon),
+                );
+                if self.update_profiles_or_restart_if_required(profiles) {
+                    self.restart_watchdog();
+                    let idx = self.read_selected_profile();
+                    self.show_selected_profile(idx);
+                }
+            }
+            Err(e) => {
+                self.tray.show(
+                    &format!("{}", e),
+                    Some("Error reloading profiles"),
+                    Some(flags),
+                    Some(&self.icon),
+                );
+            }
+        }
     }
 
     pub fn load_profiles(&self) {
         {
             let mut profiles = self.profiles.borrow_mut();
-            *profiles = store::load_profiles().unwrap();
+            *profiles = match self.store().load() {
+                Ok(profiles) => profiles,
+                Err(e) => {
+                    let flags = nwg::TrayNotificationFlags::USER_ICON
+                        | nwg::TrayNotificationFlags::LARGE_ICON;
+                    self.tray.show(
+                        &format!("{}", e),
+                        Some("Error loading profiles"),
+                        Some(flags),
+                        Some(&self.icon),
+                    );
+                    Vec::new()
+                }
+            };
 
             for profile in profiles.iter() {
                 self.add_profile_menu_item(profile);
@@ -90,10 +220,48 @@ impl KeypadTray {
         self.show_selected_profile(idx);
     }
 
+    fn restart_watchdog(&self) {
+        self.stop_watchdog();
+        self.start_watchdog();
+    }
+
     pub fn start_watchdog(&self) {
         let mut watchdog = self.watchdog.borrow_mut();
         let notice = self.watchdog_notice.sender();
-        *watchdog = Some(WatchDog::start(store::load_profiles().unwrap(), notice));
+        let profiles = self.profiles.borrow().clone();
+        let source = system_process_source(poll_interval());
+        let state = Arc::clone(&self.switch_state);
+        let audit = self.audit().clone();
+        *watchdog = Some(WatchDog::start(profiles, source, state, audit, notice));
+    }
+
+    fn toggle_auto_switch(&self) {
+        // stored here too, the watchdog isn't running when no profile has rules
+        let paused = {
+            let mut state = self.switch_state.lock().unwrap();
+            state.paused = !state.paused;
+            state.paused
+        };
+        self.pause_auto_switch.set_checked(paused);
+        self.send_to_watchdog(match paused {
+            true => Command::Pause,
+            false => Command::Resume,
+        });
+    }
+
+    // auto-switching leaves the current profile alone until the time runs out
+    fn pin_profile(&self) {
+        self.send_to_watchdog(Command::Pin(Some(PIN_DURATION)));
+    }
+
+    fn clear_override(&self) {
+        self.send_to_watchdog(Command::ClearOverride);
+    }
+
+    fn send_to_watchdog(&self, command: Command) {
+        if let Some(watchdog) = self.watchdog.borrow().as_ref() {
+            watchdog.send(command);
+        }
     }
 
     fn watchdog_notice_received(&self) {
@@ -102,7 +270,7 @@ impl KeypadTray {
             let flags =
                 nwg::TrayNotificationFlags::USER_ICON | nwg::TrayNotificationFlags::LARGE_ICON;
             self.tray.show(
-                &format!("{}", self.profiles.borrow()[idx]),
+                &self.profile_summary(idx),
                 Some("Keypad profile changed"),
                 Some(flags),
                 Some(&self.icon),
@@ -114,17 +282,30 @@ impl KeypadTray {
 
     fn read_selected_profile(&self) -> Option<usize> {
         let profiles = self.profiles.borrow();
-        let result = Keypad::auto_detect().and_then(|mut k| k.get_combos_from_device());
+        let result = Keypad::auto_detect()
+            .and_then(|mut k| Ok((k.get_profile_id()?, k.read_combos_from_device()?)));
         match result {
-            Ok(combos) => {
-                let matching_idx = profiles
-                    .iter()
-                    .enumerate()
-                    .find(|(_, p)| p.combos == combos)
-                    .map(|(i, _)| i);
+            Ok((id, combos)) => {
+                // without a known id, fall back to the profile with the same combos.
+                // a blank or corrupt slot just means no profile matches
+                let matching_idx = id
+                    .and_then(|id| profiles.iter().position(|p| p.id == id))
+                    .or_else(|| {
+                        profiles.iter().position(|p| match p.resolve(&profiles) {
+                            Ok(resolved) => resolved
+                                .iter()
+                                .zip(combos.iter())
+                                .all(|(combo, read)| read.as_ref() == Ok(combo)),
+                            Err(_) => false,
+                        })
+                    });
+                self.audit().record(Event::ReadBack {
+                    profile: matching_idx.map(|idx| profiles[idx].name.clone()),
+                });
                 return matching_idx;
             }
             Err(e) => {
+                self.audit().error("read", &e);
                 let flags =
                     nwg::TrayNotificationFlags::USER_ICON | nwg::TrayNotificationFlags::LARGE_ICON;
                 self.tray.show(
@@ -173,22 +354,50 @@ impl KeypadTray {
     fn apply_profile(&self, idx: usize) {
         let profiles = self.profiles.borrow();
         let profile = profiles[idx].clone();
-        let result =
-            Keypad::auto_detect().and_then(|mut k| k.send_combos_to_device(profile.combos));
+        self.audit().record(Event::Switch {
+            profile: profile.name.clone(),
+            reason: SwitchReason::Manual,
+        });
+        let result = match profile.resolve(&profiles) {
+            Ok(combos) => Keypad::auto_detect()
+                .and_then(|mut k| k.apply_profile(profile.id, combos, ApplyMode::Persistent))
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
         if let Err(e) = result {
+            self.audit().error("write", &e);
             let flags =
                 nwg::TrayNotificationFlags::USER_ICON | nwg::TrayNotificationFlags::LARGE_ICON;
             self.tray.show(
-                &format!("{}", e),
+                &e,
                 Some("Error applying profile"),
                 Some(flags),
                 Some(&self.icon),
             );
             return;
         }
+        self.audit().record(Event::Write {
+            profile: profile.name,
+        });
+        // keep the user's choice until auto-switching would pick something else
+        self.send_to_watchdog(Command::Pin(None));
         self.show_selected_profile(Some(idx));
     }
 
+    // the profile and what its labelled keys now do
+    fn profile_summary(&self, idx: usize) -> String {
+        let profiles = self.profiles.borrow();
+        let mut summary = profiles[idx].to_string();
+        if let Ok(labels) = profiles[idx].resolve_labels(&profiles) {
+            for (key, label) in self.board.keys.iter().zip(labels.iter()) {
+                if let Some(label) = &label.label {
+                    summary.push_str(&format!("\r\n{}: {}", key.name, label));
+                }
+            }
+        }
+        summary
+    }
+
     fn show_selected_profile(&self, idx: Option<usize>) {
         let items = self.profile_menu_items.borrow();
         for (i, item) in items.iter().enumerate() {
@@ -214,10 +423,12 @@ impl KeypadTray {
         let notice = self.editor_notice.sender();
         let profiles = self.profiles.borrow();
         let profiles: Vec<_> = profiles.iter().map(Profile::clone).collect();
+        let audit = self.audit().clone();
+        let board = self.board.clone();
 
         *handle = Some(thread::spawn(move || {
             nwg::init().unwrap();
-            let panel = ControlPanel::new(profiles);
+            let panel = ControlPanel::new(profiles, audit, board);
             let ui = ControlPanel::build_ui(panel).expect("Failed to build control panel UI");
             nwg::dispatch_thread_events();
 
@@ -233,7 +444,17 @@ impl KeypadTray {
         let mut data = self.editor_data.borrow_mut();
         if let Some(handle) = data.take() {
             let updated_profiles = handle.join().unwrap();
-            if let Err(e) = store::store_profiles(&updated_profiles) {
+            let result = match self.store().store(&updated_profiles) {
+                Err(StoreError::Conflict) => {
+                    if !self.confirm_overwrite() {
+                        self.reload_profiles();
+                        return;
+                    }
+                    self.store().overwrite(&updated_profiles)
+                }
+                result => result,
+            };
+            if let Err(e) = result {
                 let flags =
                     nwg::TrayNotificationFlags::USER_ICON | nwg::TrayNotificationFlags::LARGE_ICON;
                 self.tray.show(
@@ -245,30 +466,71 @@ impl KeypadTray {
                 return;
             }
 
-            self.update_profiles_or_restart_if_required(updated_profiles);
-            let idx = self.read_selected_profile();
-            self.show_selected_profile(idx);
+            if self.update_profiles_or_restart_if_required(updated_profiles) {
+                self.restart_watchdog();
+                let idx = self.read_selected_profile();
+                self.show_selected_profile(idx);
+            }
         }
     }
 
-    fn update_profiles_or_restart_if_required(&self, updated_profiles: Vec<Profile>) {
+    fn confirm_overwrite(&self) -> bool {
+        let params = nwg::MessageParams {
+            title: "Profiles changed",
+            content: "The profiles file was changed by another program while you were editing.\r\n\r\n\
+                Yes: save your changes and overwrite the other changes\r\n\
+                No: discard your changes and reload the profiles",
+            buttons: nwg::MessageButtons::YesNo,
+            icons: nwg::MessageIcons::Warning,
+        };
+        match nwg::message(&params) {
+            nwg::MessageChoice::Yes => true,
+            _ => false,
+        }
+    }
+
+    // returns false if the tray is restarting to rebuild the profile menu
+    fn update_profiles_or_restart_if_required(&self, updated_profiles: Vec<Profile>) -> bool {
         let mut tray_profiles = self.profiles.borrow_mut();
         if tray_profiles.len() != updated_profiles.len() {
             self.restart();
-            return;
+            return false;
         }
         for (idx, profile) in updated_profiles.iter().enumerate() {
             if Self::profile_updated(profile, &tray_profiles[idx]) {
                 self.restart();
-                return;
+                return false;
             }
         }
 
         *tray_profiles = updated_profiles;
+        true
     }
 
     fn profile_updated(lhs: &Profile, rhs: &Profile) -> bool {
-        lhs.name != rhs.name || lhs.auto_launch_program != rhs.auto_launch_program
+        lhs.name != rhs.name || lhs.auto_switch != rhs.auto_switch
+    }
+
+    fn show_recent_activity(&self) {
+        let text = match self.audit().recent(RECENT_ACTIVITY_COUNT) {
+            Ok(entries) if entries.is_empty() => "Nothing has happened yet.".to_string(),
+            Ok(entries) => entries
+                .iter()
+                .rev()
+                .map(|entry| entry.to_string())
+                .collect::<Vec<_>>()
+                .join("\r\n"),
+            Err(e) => format!("Unable to read the activity log: {}", e),
+        };
+        nwg::simple_message("Recent activity", &text);
+    }
+
+    fn audit(&self) -> &AuditLog {
+        self.audit.as_ref().expect("Audit log was not provided")
+    }
+
+    fn store(&self) -> &dyn ProfileStore {
+        self.store.as_deref().expect("Profile store was not provided")
     }
 
     fn restart(&self) {
@@ -281,6 +543,7 @@ impl KeypadTray {
         if let Some(handle) = maybe_handle.take() {
             nwg::unbind_event_handler(&handle);
         }
+        self.store_watcher.borrow_mut().take();
         self.stop_watchdog();
         nwg::stop_thread_dispatch();
     }
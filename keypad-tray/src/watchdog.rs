@@ -1,69 +1,174 @@
 use std::{
-    sync::mpsc::{channel, Sender},
-    thread::{sleep, spawn},
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+    thread::spawn,
     time::Duration,
 };
 
+use chrono::NaiveDateTime;
 use native_windows_gui::NoticeSender;
-use sysinfo::{RefreshKind, System, SystemExt};
+use uuid::Uuid;
 
-use crate::models::Profile;
+use crate::{
+    audit::{AuditLog, Event, SwitchReason},
+    focus::{system_focus_provider, FocusedWindow},
+    models::Profile,
+    processes::{ProcessFilter, ProcessSource},
+    rules::{CompiledAutoSwitch, ProcessInfo},
+    schedule::{Clock, LocalClock},
+};
+
+pub enum Command {
+    Shutdown,
+    Pause,
+    Resume,
+    // keep a manually applied profile for the given time,
+    // or with None until the switcher would pick a different profile
+    Pin(Option<Duration>),
+    ClearOverride,
+}
 
 pub struct WatchDog {
-    shutdown: Sender<()>,
+    commands: Sender<Command>,
+}
+
+// Kept outside the tray, which is rebuilt whenever profiles are renamed or reordered,
+// so a pause or a pin outlives the watchdog that was running when it was made.
+#[derive(Debug, Clone, Default)]
+pub struct SwitchState {
+    pub paused: bool,
+    pin: Option<Option<NaiveDateTime>>,
+    device: Device,
+}
+
+pub type SharedSwitchState = Arc<Mutex<SwitchState>>;
+
+// what the switcher last left on the keypad, by id because restarts reorder profiles
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Device {
+    Default,
+    AutoProfile(Uuid),
+    Overridden,
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Device::Default
+    }
 }
 
 const WATCH_INTERVAL_SECONDS: u64 = 1;
 
 impl WatchDog {
-    pub fn start(profiles: Vec<Profile>, notice: NoticeSender) -> Self {
-        let (tx, rx) = channel::<()>();
+    pub fn start(
+        profiles: Vec<Profile>,
+        mut source: Box<dyn ProcessSource + Send>,
+        state: SharedSwitchState,
+        audit: AuditLog,
+        notice: NoticeSender,
+    ) -> Self {
+        let (tx, rx) = channel::<Command>();
         spawn(move || {
-            let mut system = System::new_with_specifics(RefreshKind::new().with_processes());
-            let mut shutdown = rx.try_iter();
+            let mut focus = system_focus_provider();
+            // inheritance is resolved once, the watchdog restarts when profiles change
+            let profiles: Result<Vec<Profile>, _> =
+                profiles.iter().map(|p| p.flattened(&profiles)).collect();
+            let profiles = match profiles {
+                Ok(profiles) => profiles,
+                Err(e) => {
+                    log::warn!("Auto-switching disabled: {}", e);
+                    audit.error("auto-switch", e);
+                    return;
+                }
+            };
             let mut switcher = match AutoSwitcher::new(profiles) {
                 Some(switcher) => switcher,
                 None => return (),
             };
+            switcher.restore(&state.lock().unwrap());
+            source.watch(switcher.process_filter());
 
+            let mut processes = Vec::new();
+            let mut evaluate = true;
             loop {
-                if shutdown.next().is_some() {
-                    break;
+                for command in rx.try_iter() {
+                    match command {
+                        Command::Shutdown => return,
+                        command => switcher.command(command),
+                    }
+                    *state.lock().unwrap() = switcher.state();
+                    evaluate = true;
                 }
 
-                system.refresh_processes();
-                match switcher.next_profile(&system) {
-                    Some(profile) => {
-                        apply_profile(profile);
-                        notice.notice();
+                // process changes wake the source, focus, schedules and pins have to be polled
+                if evaluate || switcher.uses_focus() {
+                    processes = source.processes();
+                }
+                if evaluate || switcher.needs_polling() {
+                    let focused = focus.as_mut().and_then(|f| f.focused_window());
+                    match switcher.tick(&processes, focused.as_ref()) {
+                        Some(profile) => {
+                            apply_profile(profile, switcher.reason(), &audit);
+                            notice.notice();
+                        }
+                        None => {}
                     }
-                    None => {}
+                    *state.lock().unwrap() = switcher.state();
                 }
 
-                sleep(Duration::from_secs(WATCH_INTERVAL_SECONDS));
+                evaluate = source.wait(Duration::from_secs(WATCH_INTERVAL_SECONDS));
             }
         });
 
-        Self { shutdown: tx }
+        Self { commands: tx }
+    }
+
+    pub fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
     }
 
     pub fn stop(self) {
-        let _ = self.shutdown.send(());
+        self.send(Command::Shutdown);
     }
 }
 
-fn apply_profile(profile: Profile) {
-    let _ = keypad::Keypad::auto_detect().and_then(|mut k| k.send_combos_to_device(profile.combos));
+fn apply_profile(profile: Profile, reason: SwitchReason, audit: &AuditLog) {
+    audit.record(Event::Switch {
+        profile: profile.name.clone(),
+        reason,
+    });
+    let (id, combos) = (profile.id, profile.combos);
+    // auto-switches are frequent, so they are not stored and a power cycle restores the saved combos
+    let result = keypad::Keypad::auto_detect()
+        .and_then(|mut k| k.apply_profile(id, combos, keypad::ApplyMode::Volatile));
+    match result {
+        Ok(_) => audit.record(Event::Write {
+            profile: profile.name,
+        }),
+        Err(e) => {
+            log::warn!("Failed to apply profile '{}': {}", profile.name, e);
+            audit.error("write", e);
+        }
+    }
 }
 
 struct AutoSwitcher {
     default: Profile,
-    auto_profiles: Vec<Profile>,
+    auto_profiles: Vec<(Profile, CompiledAutoSwitch)>,
     state: State,
+    paused: bool,
+    pin: Option<Option<NaiveDateTime>>,
+    clock: Box<dyn Clock + Send>,
 }
 
 impl AutoSwitcher {
-    fn new(mut profiles: Vec<Profile>) -> Option<Self> {
+    fn new(profiles: Vec<Profile>) -> Option<Self> {
+        Self::with_clock(profiles, Box::new(LocalClock))
+    }
+
+    fn with_clock(mut profiles: Vec<Profile>, clock: Box<dyn Clock + Send>) -> Option<Self> {
         if profiles.len() == 0 {
             return None;
         }
@@ -71,54 +176,710 @@ impl AutoSwitcher {
         let default = profiles.remove(0);
         let auto_profiles = profiles
             .into_iter()
-            .filter(|p| {
-                p.auto_launch_program.is_some()
-                    && p.auto_launch_program.as_ref().unwrap().trim().len() > 0
+            .filter_map(|p| {
+                let rules = match p.auto_switch.as_ref()?.compile() {
+                    Ok(rules) => rules,
+                    Err(e) => {
+                        log::warn!("Ignoring auto-switch rules for '{}': {}", p.name, e);
+                        return None;
+                    }
+                };
+                Some((p, rules))
             })
             .collect();
         Some(Self {
             state: State::Default,
             default,
             auto_profiles,
+            paused: false,
+            pin: None,
+            clock,
         })
     }
 
-    fn next_profile(&mut self, system: &System) -> Option<Profile> {
-        match self.state {
-            State::Default => {
-                let profile = self
-                    .auto_profiles
-                    .iter()
-                    .find(|p| {
-                        let name = p.auto_launch_program.as_ref().unwrap();
-                        program_is_running(name.as_ref(), system)
-                    })
-                    .map(|p| p.clone());
-                if profile.is_some() {
-                    self.state = State::InProgram(profile.clone().unwrap());
-                }
-                profile
+    fn command(&mut self, command: Command) {
+        match command {
+            Command::Pause => self.paused = true,
+            Command::Resume => {
+                self.paused = false;
+                self.override_ended();
+            }
+            Command::Pin(duration) => {
+                let now = self.clock.now();
+                let until = duration
+                    .and_then(|d| chrono::Duration::from_std(d).ok())
+                    .and_then(|d| now.checked_add_signed(d));
+                self.pin = Some(until);
+            }
+            Command::ClearOverride => {
+                self.pin = None;
+                self.override_ended();
+            }
+            Command::Shutdown => {}
+        }
+    }
+
+    fn state(&self) -> SwitchState {
+        SwitchState {
+            paused: self.paused,
+            pin: self.pin,
+            device: match self.state {
+                State::Default => Device::Default,
+                State::InProgram(idx) => Device::AutoProfile(self.auto_profiles[idx].0.id),
+                State::Overridden => Device::Overridden,
+            },
+        }
+    }
+
+    // a profile that no longer has rules can't be tracked, so it counts as overridden
+    fn restore(&mut self, saved: &SwitchState) {
+        self.paused = saved.paused;
+        self.pin = saved.pin;
+        self.state = match saved.device {
+            Device::Default => State::Default,
+            Device::AutoProfile(id) => self
+                .auto_profiles
+                .iter()
+                .position(|(p, _)| p.id == id)
+                .map_or(State::Overridden, State::InProgram),
+            Device::Overridden => State::Overridden,
+        };
+    }
+
+    // the device may hold anything now, so the next tick applies whatever matches
+    fn override_ended(&mut self) {
+        if !self.paused && self.pin.is_none() {
+            self.state = State::Overridden;
+        }
+    }
+
+    fn tick(
+        &mut self,
+        processes: &[ProcessInfo],
+        focused: Option<&FocusedWindow>,
+    ) -> Option<Profile> {
+        if let Some(Some(until)) = self.pin {
+            if self.clock.now() >= until {
+                self.pin = None;
+                self.override_ended();
             }
-            State::InProgram(ref p) => {
-                let name = p.auto_launch_program.as_ref().unwrap();
-                if !program_is_running(name.as_ref(), system) {
-                    // program has stopped, set to default and run again
-                    // to check if other programs are running
-                    self.state = State::Default;
-                    self.next_profile(system).or(Some(self.default.clone()))
-                } else {
-                    None
+        }
+
+        // keep tracking while overridden so the pin knows when things change
+        let next = self.next_profile(processes, focused);
+        if next.is_some() && self.pin == Some(None) {
+            self.pin = None;
+        }
+        match self.paused || self.pin.is_some() {
+            true => None,
+            false => next,
+        }
+    }
+
+    fn next_profile(
+        &mut self,
+        processes: &[ProcessInfo],
+        focused: Option<&FocusedWindow>,
+    ) -> Option<Profile> {
+        let now = self.clock.now();
+        let current = match self.state {
+            State::Default | State::Overridden => None,
+            State::InProgram(idx) => {
+                let rules = &self.auto_profiles[idx].1;
+                if rules.sticky && rules.matches(processes, focused, now) {
+                    return None;
                 }
+                Some(idx)
+            }
+        };
+
+        let best = self.best_match(processes, focused, now, current);
+        if best == current && self.state != State::Overridden {
+            return None;
+        }
+        match best {
+            Some(idx) => {
+                self.state = State::InProgram(idx);
+                Some(self.auto_profiles[idx].0.clone())
+            }
+            None => {
+                self.state = State::Default;
+                Some(self.default.clone())
             }
         }
     }
-}
 
-fn program_is_running(name: &str, system: &System) -> bool {
-    system.get_process_by_name(name).len() > 0
+    fn reason(&self) -> SwitchReason {
+        match self.state {
+            State::InProgram(idx) => SwitchReason::Rules {
+                rules: self.auto_profiles[idx]
+                    .0
+                    .auto_switch
+                    .as_ref()
+                    .map(|a| a.to_string())
+                    .unwrap_or_default(),
+            },
+            _ => SwitchReason::Default,
+        }
+    }
+
+    fn needs_polling(&self) -> bool {
+        let timed_pin = match self.pin {
+            Some(Some(_)) => true,
+            _ => false,
+        };
+        let uses_schedule = self
+            .auto_profiles
+            .iter()
+            .any(|(_, rules)| rules.uses_schedule());
+        timed_pin || uses_schedule || self.uses_focus()
+    }
+
+    fn uses_focus(&self) -> bool {
+        self.auto_profiles
+            .iter()
+            .any(|(_, rules)| rules.uses_focus())
+    }
+
+    fn process_filter(&self) -> ProcessFilter {
+        let rules: Vec<CompiledAutoSwitch> = self
+            .auto_profiles
+            .iter()
+            .map(|(_, rules)| rules.clone())
+            .collect();
+        Box::new(move |process| rules.iter().any(|rules| rules.watches(process)))
+    }
+
+    // highest priority wins, ties keep the current profile and then go by list order,
+    // whether a profile matched on processes, focus or schedule doesn't matter
+    fn best_match(
+        &self,
+        processes: &[ProcessInfo],
+        focused: Option<&FocusedWindow>,
+        now: NaiveDateTime,
+        current: Option<usize>,
+    ) -> Option<usize> {
+        let mut best: Option<usize> = None;
+        for (idx, (_, rules)) in self.auto_profiles.iter().enumerate() {
+            if !rules.matches(processes, focused, now) {
+                continue;
+            }
+            let better = match best {
+                None => true,
+                Some(best) => {
+                    let priority = self.auto_profiles[best].1.priority;
+                    rules.priority > priority
+                        || (rules.priority == priority && current == Some(idx))
+                }
+            };
+            if better {
+                best = Some(idx);
+            }
+        }
+        best
+    }
 }
 
+#[derive(PartialEq)]
 enum State {
     Default,
-    InProgram(Profile),
+    InProgram(usize),
+    // the device holds a profile the switcher didn't choose
+    Overridden,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        focus::{FakeFocusProvider, FocusProvider},
+        processes::ScriptedProcessSource,
+        rules::{AutoSwitch, Trigger},
+        schedule::FakeClock,
+    };
+
+    fn profile(name: &str, auto_switch: Option<&str>, trigger: Trigger) -> Profile {
+        Profile {
+            name: name.into(),
+            auto_switch: auto_switch.map(|rules| AutoSwitch {
+                trigger,
+                ..rules.parse().unwrap()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn ranked(name: &str, program: &str, priority: i32, sticky: bool) -> Profile {
+        Profile {
+            name: name.into(),
+            auto_switch: Some(AutoSwitch {
+                priority,
+                sticky,
+                ..AutoSwitch::process(program)
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn process(pid: u32, name: &str) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    fn window(pid: u32, title: &str) -> Option<FocusedWindow> {
+        Some(FocusedWindow {
+            pid,
+            title: title.into(),
+        })
+    }
+
+    fn name(profile: Option<Profile>) -> Option<String> {
+        profile.map(|p| p.name)
+    }
+
+    // runs one tick per script entry and collects the profile switched to on each
+    fn run(profiles: Vec<Profile>, script: Vec<Vec<&str>>) -> Vec<Option<String>> {
+        let ticks = script.len();
+        let mut source = ScriptedProcessSource::new(script);
+        let mut switcher = AutoSwitcher::new(profiles).unwrap();
+        (0..ticks)
+            .map(|_| name(switcher.next_profile(&source.processes(), None)))
+            .collect()
+    }
+
+    fn switched(name: &str) -> Option<String> {
+        Some(name.into())
+    }
+
+    fn auto_profiles() -> Vec<Profile> {
+        vec![
+            profile("Default", None, Trigger::Running),
+            profile("Photoshop", Some("photoshop.exe"), Trigger::Running),
+            profile("OBS", Some("obs64.exe"), Trigger::Running),
+        ]
+    }
+
+    #[test]
+    fn stays_on_default_without_matching_programs() {
+        let switches = run(
+            auto_profiles(),
+            vec![
+                vec![],
+                vec!["explorer.exe"],
+                vec!["explorer.exe", "code.exe"],
+            ],
+        );
+        assert_eq!(switches, vec![None, None, None]);
+    }
+
+    #[test]
+    fn switches_when_program_starts() {
+        let switches = run(
+            auto_profiles(),
+            vec![
+                vec!["explorer.exe"],
+                vec!["explorer.exe", "Photoshop.exe"],
+                vec!["explorer.exe", "Photoshop.exe"],
+            ],
+        );
+        assert_eq!(switches, vec![None, switched("Photoshop"), None]);
+    }
+
+    #[test]
+    fn switches_between_programs() {
+        let switches = run(
+            auto_profiles(),
+            vec![
+                vec!["photoshop.exe"],
+                vec!["obs64.exe"],
+                vec!["obs64.exe", "photoshop.exe"],
+                vec!["photoshop.exe"],
+            ],
+        );
+        assert_eq!(
+            switches,
+            vec![
+                switched("Photoshop"),
+                switched("OBS"),
+                None,
+                switched("Photoshop")
+            ]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_default_when_program_exits() {
+        let switches = run(
+            auto_profiles(),
+            vec![vec!["obs64.exe"], vec![], vec![], vec!["obs64.exe"]],
+        );
+        assert_eq!(
+            switches,
+            vec![switched("OBS"), switched("Default"), None, switched("OBS")]
+        );
+    }
+
+    #[test]
+    fn profiles_without_rules_never_match() {
+        let profiles = vec![
+            profile("Default", None, Trigger::Running),
+            profile("Manual", None, Trigger::Running),
+        ];
+        let switches = run(profiles, vec![vec!["anything.exe"], vec![]]);
+        assert_eq!(switches, vec![None, None]);
+    }
+
+    #[test]
+    fn focused_profile_ignores_background_programs() {
+        let profiles = vec![
+            profile("Default", None, Trigger::Running),
+            profile("Photoshop", Some("photoshop.exe"), Trigger::Focused),
+        ];
+        let mut switcher = AutoSwitcher::new(profiles).unwrap();
+        let processes = vec![process(1, "explorer.exe"), process(2, "Photoshop.exe")];
+        let mut focus = FakeFocusProvider {
+            window: window(1, "Downloads"),
+        };
+
+        let focused = focus.focused_window();
+        assert_eq!(
+            name(switcher.next_profile(&processes, focused.as_ref())),
+            None
+        );
+
+        focus.window = window(2, "Untitled-1 @ 100%");
+        let focused = focus.focused_window();
+        assert_eq!(
+            name(switcher.next_profile(&processes, focused.as_ref())),
+            Some("Photoshop".into())
+        );
+
+        focus.window = window(1, "Downloads");
+        let focused = focus.focused_window();
+        assert_eq!(
+            name(switcher.next_profile(&processes, focused.as_ref())),
+            Some("Default".into())
+        );
+    }
+
+    #[test]
+    fn focused_profile_matches_window_title() {
+        let profiles = vec![
+            profile("Default", None, Trigger::Running),
+            profile("Meeting", Some("title:*Zoom Meeting*"), Trigger::Focused),
+        ];
+        let mut switcher = AutoSwitcher::new(profiles).unwrap();
+        let processes = vec![process(7, "zoom.exe")];
+        let mut focus = FakeFocusProvider {
+            window: window(7, "Zoom Meeting - Standup"),
+        };
+
+        let focused = focus.focused_window();
+        assert_eq!(
+            name(switcher.next_profile(&processes, focused.as_ref())),
+            Some("Meeting".into())
+        );
+        assert_eq!(
+            name(switcher.next_profile(&processes, None)),
+            Some("Default".into())
+        );
+    }
+
+    #[test]
+    fn highest_priority_match_wins() {
+        let profiles = vec![
+            profile("Default", None, Trigger::Running),
+            ranked("Browser", "firefox.exe", 0, false),
+            ranked("Game", "game.exe", 10, false),
+        ];
+        let mut switcher = AutoSwitcher::new(profiles).unwrap();
+        let processes = vec![process(1, "firefox.exe"), process(2, "game.exe")];
+
+        assert_eq!(
+            name(switcher.next_profile(&processes, None)),
+            Some("Game".into())
+        );
+        assert_eq!(name(switcher.next_profile(&processes, None)), None);
+    }
+
+    #[test]
+    fn higher_priority_program_preempts_current_profile() {
+        let profiles = vec![
+            profile("Default", None, Trigger::Running),
+            ranked("Browser", "firefox.exe", 0, false),
+            ranked("Game", "game.exe", 10, false),
+        ];
+        let mut switcher = AutoSwitcher::new(profiles).unwrap();
+        let mut processes = vec![process(1, "firefox.exe")];
+
+        assert_eq!(
+            name(switcher.next_profile(&processes, None)),
+            Some("Browser".into())
+        );
+
+        processes.push(process(2, "game.exe"));
+        assert_eq!(
+            name(switcher.next_profile(&processes, None)),
+            Some("Game".into())
+        );
+
+        // falls back to the lower priority match rather than the default
+        processes.remove(1);
+        assert_eq!(
+            name(switcher.next_profile(&processes, None)),
+            Some("Browser".into())
+        );
+
+        processes.clear();
+        assert_eq!(
+            name(switcher.next_profile(&processes, None)),
+            Some("Default".into())
+        );
+    }
+
+    #[test]
+    fn equal_priority_keeps_current_profile() {
+        let profiles = vec![
+            profile("Default", None, Trigger::Running),
+            ranked("Editor", "code.exe", 5, false),
+            ranked("Terminal", "wt.exe", 5, false),
+        ];
+        let mut switcher = AutoSwitcher::new(profiles).unwrap();
+        let mut processes = vec![process(1, "wt.exe")];
+
+        assert_eq!(
+            name(switcher.next_profile(&processes, None)),
+            Some("Terminal".into())
+        );
+
+        processes.push(process(2, "code.exe"));
+        assert_eq!(name(switcher.next_profile(&processes, None)), None);
+    }
+
+    #[test]
+    fn sticky_profile_is_not_preempted() {
+        let profiles = vec![
+            profile("Default", None, Trigger::Running),
+            ranked("Recording", "obs64.exe", 0, true),
+            ranked("Game", "game.exe", 10, false),
+        ];
+        let mut switcher = AutoSwitcher::new(profiles).unwrap();
+        let mut processes = vec![process(1, "obs64.exe")];
+
+        assert_eq!(
+            name(switcher.next_profile(&processes, None)),
+            Some("Recording".into())
+        );
+
+        processes.push(process(2, "game.exe"));
+        assert_eq!(name(switcher.next_profile(&processes, None)), None);
+
+        processes.remove(0);
+        assert_eq!(
+            name(switcher.next_profile(&processes, None)),
+            Some("Game".into())
+        );
+    }
+
+    #[test]
+    fn only_programs_in_rules_are_watched() {
+        let profiles = vec![
+            profile("Default", None, Trigger::Running),
+            profile(
+                "Editor",
+                Some("code*.exe | args:--profile=work"),
+                Trigger::Running,
+            ),
+            profile("Meeting", Some("title:*Zoom*"), Trigger::Focused),
+        ];
+        let switcher = AutoSwitcher::new(profiles).unwrap();
+        let filter = switcher.process_filter();
+
+        assert!(filter(&process(1, "code-insiders.exe")));
+        assert!(filter(&ProcessInfo {
+            cmd: vec!["firefox.exe".into(), "--profile=work".into()],
+            ..process(2, "firefox.exe")
+        }));
+        assert!(!filter(&process(3, "zoom.exe")));
+        assert!(!filter(&process(4, "explorer.exe")));
+        assert!(switcher.uses_focus());
+    }
+
+    #[test]
+    fn manual_pin_holds_until_switcher_moves_on() {
+        let mut switcher = AutoSwitcher::new(auto_profiles()).unwrap();
+        let mut source = ScriptedProcessSource::new(vec![
+            vec!["photoshop.exe"],
+            vec!["photoshop.exe", "explorer.exe"],
+            vec!["obs64.exe"],
+        ]);
+
+        assert_eq!(
+            name(switcher.tick(&source.processes(), None)),
+            switched("Photoshop")
+        );
+        switcher.command(Command::Pin(None));
+        assert_eq!(name(switcher.tick(&source.processes(), None)), None);
+        assert_eq!(
+            name(switcher.tick(&source.processes(), None)),
+            switched("OBS")
+        );
+    }
+
+    #[test]
+    fn pin_and_pause_survive_a_restart() {
+        let mut profiles = auto_profiles();
+        let mut switcher = AutoSwitcher::new(profiles.clone()).unwrap();
+        let photoshop = vec![process(1, "photoshop.exe")];
+        assert_eq!(name(switcher.tick(&photoshop, None)), switched("Photoshop"));
+        switcher.command(Command::Pin(None));
+
+        // profiles were reordered, the pin still holds until something else matches
+        profiles.swap(1, 2);
+        let mut restarted = AutoSwitcher::new(profiles.clone()).unwrap();
+        restarted.restore(&switcher.state());
+        assert_eq!(name(restarted.tick(&photoshop, None)), None);
+        assert_eq!(
+            name(restarted.tick(&[process(2, "obs64.exe")], None)),
+            switched("OBS")
+        );
+
+        restarted.command(Command::Pause);
+        let mut restarted_again = AutoSwitcher::new(profiles).unwrap();
+        restarted_again.restore(&restarted.state());
+        assert_eq!(name(restarted_again.tick(&photoshop, None)), None);
+        assert!(restarted_again.state().paused);
+    }
+
+    #[test]
+    fn timed_pin_expires() {
+        let clock = FakeClock::at("2021-03-01 09:00");
+        let mut switcher =
+            AutoSwitcher::with_clock(auto_profiles(), Box::new(clock.clone())).unwrap();
+        let processes = vec![process(1, "photoshop.exe")];
+
+        switcher.command(Command::Pin(Some(Duration::from_secs(60))));
+        assert_eq!(name(switcher.tick(&processes, None)), None);
+        assert_eq!(name(switcher.tick(&processes, None)), None);
+        clock.set("2021-03-01 09:01");
+        assert_eq!(name(switcher.tick(&processes, None)), switched("Photoshop"));
+        assert_eq!(name(switcher.tick(&processes, None)), None);
+    }
+
+    #[test]
+    fn paused_switcher_reapplies_on_resume() {
+        let mut switcher = AutoSwitcher::new(auto_profiles()).unwrap();
+
+        switcher.command(Command::Pause);
+        assert_eq!(name(switcher.tick(&[process(1, "obs64.exe")], None)), None);
+        assert_eq!(name(switcher.tick(&[], None)), None);
+
+        // the profile applied while paused is unknown, so resuming applies the default
+        switcher.command(Command::Resume);
+        assert_eq!(name(switcher.tick(&[], None)), switched("Default"));
+        assert_eq!(name(switcher.tick(&[], None)), None);
+    }
+
+    #[test]
+    fn clearing_pin_keeps_pause() {
+        let mut switcher = AutoSwitcher::new(auto_profiles()).unwrap();
+        let processes = vec![process(1, "obs64.exe")];
+
+        switcher.command(Command::Pause);
+        switcher.command(Command::Pin(None));
+        switcher.command(Command::ClearOverride);
+        assert_eq!(name(switcher.tick(&processes, None)), None);
+
+        switcher.command(Command::Resume);
+        assert_eq!(name(switcher.tick(&processes, None)), switched("OBS"));
+    }
+
+    #[test]
+    fn scheduled_profile_switches_on_time() {
+        let profiles = vec![
+            profile("Default", None, Trigger::Running),
+            profile(
+                "Meeting",
+                Some("time:Mon-Fri 09:00-09:30"),
+                Trigger::Running,
+            ),
+        ];
+        // 2021-03-01 was a Monday
+        let clock = FakeClock::at("2021-03-01 08:59");
+        let mut switcher = AutoSwitcher::with_clock(profiles, Box::new(clock.clone())).unwrap();
+
+        assert_eq!(name(switcher.next_profile(&[], None)), None);
+        clock.set("2021-03-01 09:00");
+        assert_eq!(name(switcher.next_profile(&[], None)), switched("Meeting"));
+        clock.set("2021-03-01 09:30");
+        assert_eq!(name(switcher.next_profile(&[], None)), switched("Default"));
+        clock.set("2021-03-06 09:00");
+        assert_eq!(name(switcher.next_profile(&[], None)), None);
+    }
+
+    #[test]
+    fn schedule_and_programs_compete_by_priority() {
+        let meeting = Profile {
+            name: "Meeting".into(),
+            auto_switch: Some(AutoSwitch {
+                priority: 5,
+                .."time:09:00-10:00".parse().unwrap()
+            }),
+            ..Default::default()
+        };
+        let profiles = vec![
+            profile("Default", None, Trigger::Running),
+            ranked("Game", "game.exe", 10, false),
+            meeting,
+            ranked("Browser", "firefox.exe", 0, false),
+        ];
+        let clock = FakeClock::at("2021-03-01 09:15");
+        let mut switcher = AutoSwitcher::with_clock(profiles, Box::new(clock.clone())).unwrap();
+
+        let browsing = vec![process(1, "firefox.exe")];
+        assert_eq!(
+            name(switcher.next_profile(&browsing, None)),
+            switched("Meeting")
+        );
+        let gaming = vec![process(1, "firefox.exe"), process(2, "game.exe")];
+        assert_eq!(name(switcher.next_profile(&gaming, None)), switched("Game"));
+        assert_eq!(
+            name(switcher.next_profile(&browsing, None)),
+            switched("Meeting")
+        );
+        clock.set("2021-03-01 10:00");
+        assert_eq!(
+            name(switcher.next_profile(&browsing, None)),
+            switched("Browser")
+        );
+    }
+
+    #[test]
+    fn all_mode_combines_schedule_and_program() {
+        let profiles = vec![
+            profile("Default", None, Trigger::Running),
+            profile(
+                "Work editor",
+                Some("code.exe & time:Mon-Fri 08:00-17:00"),
+                Trigger::Running,
+            ),
+        ];
+        let clock = FakeClock::at("2021-03-01 12:00");
+        let mut switcher = AutoSwitcher::with_clock(profiles, Box::new(clock.clone())).unwrap();
+
+        assert_eq!(name(switcher.next_profile(&[], None)), None);
+        let editing = vec![process(1, "code.exe")];
+        assert_eq!(
+            name(switcher.next_profile(&editing, None)),
+            switched("Work editor")
+        );
+        clock.set("2021-03-01 18:00");
+        assert_eq!(
+            name(switcher.next_profile(&editing, None)),
+            switched("Default")
+        );
+    }
 }